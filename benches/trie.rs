@@ -0,0 +1,140 @@
+use arca::{Path, Trie};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use radix_trie::TrieCommon;
+
+// Mirror of the former `arca::Trie` implementation, kept around to compare
+// the path radix tree against a generic radix trie.
+#[derive(Default)]
+struct RadixTrie<T> {
+    inner: radix_trie::Trie<String, (Path, T)>,
+}
+
+impl<T> RadixTrie<T> {
+    fn key(key: &Path) -> String {
+        let mut p = key.to_string();
+
+        if !p.ends_with('/') {
+            p.push('/');
+        }
+
+        p
+    }
+
+    fn get(&self, key: &Path) -> Option<&T> {
+        self.inner.get(&Self::key(key)).map(|t| &t.1)
+    }
+
+    fn get_ancestor_record(&self, key: &Path) -> Option<(&String, &Path, &T)> {
+        self.inner.get_ancestor(&Self::key(key)).map(|e| {
+            let k = e.key().unwrap();
+            let v = e.value().unwrap();
+
+            (k, &v.0, &v.1)
+        })
+    }
+
+    fn insert(&mut self, key: Path, value: T) {
+        let k = Self::key(&key);
+        let p = Path::from(k.clone());
+
+        self.inner.insert(k, (p, value));
+    }
+}
+
+fn make_keys(count: usize) -> Vec<Path> {
+    (0..count)
+        .map(|n| Path::from(format!("/repo/packages/pkg-{}/node_modules/dep-{}", n / 20, n % 20)))
+        .collect()
+}
+
+fn make_queries(count: usize) -> Vec<Path> {
+    (0..count)
+        .map(|n| Path::from(format!("/repo/packages/pkg-{}/node_modules/dep-{}/lib/index.js", n / 20, n % 20)))
+        .collect()
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+
+    for count in [1_000, 100_000] {
+        let keys = make_keys(count);
+
+        group.bench_with_input(BenchmarkId::new("arca", count), &keys, |b, keys| b.iter(|| {
+            let mut trie = Trie::default();
+
+            for (n, key) in keys.iter().enumerate() {
+                trie.insert(key.clone(), n);
+            }
+
+            trie
+        }));
+
+        group.bench_with_input(BenchmarkId::new("radix_trie", count), &keys, |b, keys| b.iter(|| {
+            let mut trie = RadixTrie::default();
+
+            for (n, key) in keys.iter().enumerate() {
+                trie.insert(key.clone(), n);
+            }
+
+            trie
+        }));
+    }
+
+    group.finish();
+}
+
+fn bench_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+
+    for count in [1_000, 100_000] {
+        let keys = make_keys(count);
+
+        let mut trie = Trie::default();
+        let mut radix = RadixTrie::default();
+
+        for (n, key) in keys.iter().enumerate() {
+            trie.insert(key.clone(), n);
+            radix.insert(key.clone(), n);
+        }
+
+        group.bench_with_input(BenchmarkId::new("arca", count), &keys, |b, keys| b.iter(|| {
+            keys.iter().filter_map(|key| trie.get(key)).count()
+        }));
+
+        group.bench_with_input(BenchmarkId::new("radix_trie", count), &keys, |b, keys| b.iter(|| {
+            keys.iter().filter_map(|key| radix.get(key)).count()
+        }));
+    }
+
+    group.finish();
+}
+
+fn bench_get_ancestor(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_ancestor_record");
+
+    for count in [1_000, 100_000] {
+        let keys = make_keys(count);
+        let queries = make_queries(count);
+
+        let mut trie = Trie::default();
+        let mut radix = RadixTrie::default();
+
+        for (n, key) in keys.iter().enumerate() {
+            trie.insert(key.clone(), n);
+            radix.insert(key.clone(), n);
+        }
+
+        group.bench_with_input(BenchmarkId::new("arca", count), &queries, |b, queries| b.iter(|| {
+            queries.iter().filter_map(|query| trie.get_ancestor_record(query)).count()
+        }));
+
+        group.bench_with_input(BenchmarkId::new("radix_trie", count), &queries, |b, queries| b.iter(|| {
+            queries.iter().filter_map(|query| radix.get_ancestor_record(query)).count()
+        }));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_get, bench_get_ancestor);
+criterion_main!(benches);
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::{fs, io};
+
+use crate::changeset::{ChangesetErr, ChangesetMode, ChangesetReport};
+use crate::{AtomicWriteOptions, ChangeOutcome, ContentHasher, CopyOptions, FileLock, HashTreeOptions, ImmutableErr, LockOptions, Path, SyncEntry, ToArcaPath};
+
+/// Runs a blocking operation on tokio's blocking thread pool. Used for the
+/// operations made of multiple syscalls, which tokio doesn't provide.
+async fn blocking<T, E, F>(f: F) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<io::Error> + Send + 'static,
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await
+        .map_err(io::Error::other)?
+}
+
+/// Async versions of the `fs_*` methods, to use from within a tokio runtime.
+/// The directory walkers have their own async counterpart, `fs_walk_stream`.
+/// Locks are acquired on the blocking thread pool, which a blocking lock
+/// keeps busy until acquired; the returned guards are the same as the sync
+/// ones.
+impl Path {
+    pub async fn fs_create_parent_async(&self) -> io::Result<&Self> {
+        if let Some(parent) = self.dirname() {
+            parent.fs_create_dir_all_async().await?;
+        }
+
+        Ok(self)
+    }
+
+    pub async fn fs_create_dir_all_async(&self) -> io::Result<&Self> {
+        tokio::fs::create_dir_all(self.to_path_buf()).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_create_dir_async(&self) -> io::Result<&Self> {
+        tokio::fs::create_dir(self.to_path_buf()).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_set_permissions_async(&self, permissions: fs::Permissions) -> io::Result<&Self> {
+        tokio::fs::set_permissions(self.to_path_buf(), permissions).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_metadata_async(&self) -> io::Result<fs::Metadata> {
+        tokio::fs::metadata(self.to_path_buf()).await
+    }
+
+    pub async fn fs_symlink_metadata_async(&self) -> io::Result<fs::Metadata> {
+        tokio::fs::symlink_metadata(self.to_path_buf()).await
+    }
+
+    pub async fn fs_exists_async(&self) -> bool {
+        self.fs_metadata_async().await.is_ok()
+    }
+
+    pub async fn fs_is_file_async(&self) -> bool {
+        self.fs_metadata_async().await.map(|m| m.is_file()).unwrap_or(false)
+    }
+
+    pub async fn fs_is_dir_async(&self) -> bool {
+        self.fs_metadata_async().await.map(|m| m.is_dir()).unwrap_or(false)
+    }
+
+    pub async fn fs_is_symlink_async(&self) -> bool {
+        self.fs_symlink_metadata_async().await.map(|m| m.file_type().is_symlink()).unwrap_or(false)
+    }
+
+    pub async fn fs_read_async(&self) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.to_path_buf()).await
+    }
+
+    pub async fn fs_read_prealloc_async(&self) -> io::Result<Vec<u8>> {
+        let path = self.clone();
+
+        blocking(move || path.fs_read_prealloc()).await
+    }
+
+    pub async fn fs_read_with_size_async(&self, size: u64) -> io::Result<Vec<u8>> {
+        let path = self.clone();
+
+        blocking(move || path.fs_read_with_size(size)).await
+    }
+
+    pub async fn fs_read_text_async(&self) -> io::Result<String> {
+        tokio::fs::read_to_string(self.to_path_buf()).await
+    }
+
+    pub async fn fs_read_text_prealloc_async(&self) -> io::Result<String> {
+        let path = self.clone();
+
+        blocking(move || path.fs_read_text_prealloc()).await
+    }
+
+    pub async fn fs_read_text_with_size_async(&self, size: u64) -> io::Result<String> {
+        let path = self.clone();
+
+        blocking(move || path.fs_read_text_with_size(size)).await
+    }
+
+    pub async fn fs_read_dir_async(&self) -> io::Result<tokio::fs::ReadDir> {
+        tokio::fs::read_dir(self.to_path_buf()).await
+    }
+
+    pub async fn fs_read_dir_entries_async(&self) -> io::Result<Vec<(Path, fs::FileType)>> {
+        let mut read_dir = self.fs_read_dir_async().await?;
+        let mut entries = Vec::new();
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            entries.push((self.with_entry_name(&entry.file_name().to_string_lossy()), file_type));
+        }
+
+        Ok(entries)
+    }
+
+    pub async fn fs_read_dir_entries_sorted_async(&self) -> io::Result<Vec<(Path, fs::FileType)>> {
+        let mut entries = self.fs_read_dir_entries_async().await?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    pub async fn fs_read_dir_names_async(&self) -> io::Result<Vec<String>> {
+        let mut read_dir = self.fs_read_dir_async().await?;
+        let mut names = Vec::new();
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+
+        Ok(names)
+    }
+
+    pub async fn fs_write_async<T: AsRef<[u8]>>(&self, data: T) -> io::Result<&Self> {
+        tokio::fs::write(self.to_path_buf(), data).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_write_text_async<T: AsRef<str>>(&self, text: T) -> io::Result<&Self> {
+        tokio::fs::write(self.to_path_buf(), text.as_ref()).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_write_atomic_async<T: AsRef<[u8]>>(&self, data: T) -> io::Result<&Self> {
+        let path = self.clone();
+        let data = data.as_ref().to_vec();
+
+        blocking(move || path.fs_write_atomic(data).map(|_| ())).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_write_atomic_with_async<T: AsRef<[u8]>>(&self, data: T, options: &AtomicWriteOptions) -> io::Result<&Self> {
+        let path = self.clone();
+        let data = data.as_ref().to_vec();
+        let options = options.clone();
+
+        blocking(move || path.fs_write_atomic_with(data, &options).map(|_| ())).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_expect_async<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> Result<&Self, ImmutableErr> {
+        let path = self.clone();
+        let data = data.as_ref().to_vec();
+
+        blocking(move || path.fs_expect(data, permissions).map(|_| ())).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_expect_with_diff_async<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> Result<&Self, ImmutableErr> {
+        let path = self.clone();
+        let data = data.as_ref().to_vec();
+
+        blocking(move || path.fs_expect_with_diff(data, permissions).map(|_| ())).await?;
+        Ok(self)
+    }
+
+    /// The reader is consumed on the blocking thread pool.
+    pub async fn fs_expect_from_reader_async<R: Read + Send + 'static>(&self, reader: R, permissions: fs::Permissions) -> Result<&Self, ImmutableErr> {
+        let path = self.clone();
+
+        blocking(move || path.fs_expect_from_reader(reader, permissions).map(|_| ())).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_expect_digest_async<H: ContentHasher + Send + 'static>(&self, digest: &[u8], hasher: H, permissions: fs::Permissions) -> Result<&Self, ImmutableErr> {
+        let path = self.clone();
+        let digest = digest.to_vec();
+
+        blocking(move || path.fs_expect_digest(&digest, hasher, permissions).map(|_| ())).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_hash_async<H: ContentHasher + Send + 'static>(&self, hasher: H) -> io::Result<Vec<u8>> {
+        let path = self.clone();
+
+        blocking(move || path.fs_hash(hasher)).await
+    }
+
+    pub async fn fs_hash_tree_async<H: ContentHasher + Send + 'static>(&self, hasher: H, options: &HashTreeOptions) -> io::Result<Vec<u8>> {
+        let path = self.clone();
+        let options = options.clone();
+
+        blocking(move || path.fs_hash_tree(hasher, &options)).await
+    }
+
+    pub async fn fs_change_async<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> io::Result<ChangeOutcome> {
+        let path = self.clone();
+        let data = data.as_ref().to_vec();
+
+        blocking(move || path.fs_change(data, permissions)).await
+    }
+
+    pub async fn fs_change_atomic_async<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> io::Result<ChangeOutcome> {
+        let path = self.clone();
+        let data = data.as_ref().to_vec();
+
+        blocking(move || path.fs_change_atomic(data, permissions)).await
+    }
+
+    /// The reader is consumed on the blocking thread pool.
+    pub async fn fs_change_from_reader_async<R: Read + Send + 'static>(&self, reader: R, permissions: fs::Permissions) -> io::Result<ChangeOutcome> {
+        let path = self.clone();
+
+        blocking(move || path.fs_change_from_reader(reader, permissions)).await
+    }
+
+    /// The reader is consumed on the blocking thread pool.
+    pub async fn fs_change_with_digest_async<R: Read + Send + 'static, H: ContentHasher + Send + 'static>(&self, digest: &[u8], hasher: H, reader: R, permissions: fs::Permissions) -> io::Result<ChangeOutcome> {
+        let path = self.clone();
+        let digest = digest.to_vec();
+
+        blocking(move || path.fs_change_with_digest(&digest, hasher, reader, permissions)).await
+    }
+
+    pub async fn fs_sync_dir_async(&self, entries: &BTreeMap<Path, SyncEntry>, mode: ChangesetMode) -> Result<ChangesetReport, ChangesetErr> {
+        let path = self.clone();
+        let entries = entries.clone();
+
+        blocking(move || path.fs_sync_dir(&entries, mode)).await
+    }
+
+    pub async fn fs_copy_async(&self, destination: &Path, options: &CopyOptions) -> io::Result<&Self> {
+        let path = self.clone();
+        let destination = destination.clone();
+        let options = options.clone();
+
+        blocking(move || path.fs_copy(&destination, &options).map(|_| ())).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_symlink_async(&self, target: &Path) -> io::Result<&Self> {
+        let path = self.clone();
+        let target = target.clone();
+
+        blocking(move || path.fs_symlink(&target).map(|_| ())).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_symlink_relative_async(&self, target: &Path) -> io::Result<&Self> {
+        self.fs_symlink_async(&self.checked_relative_link_target(target)?).await
+    }
+
+    pub async fn fs_read_link_async(&self) -> io::Result<Path> {
+        Ok(tokio::fs::read_link(self.to_path_buf()).await?.to_arca())
+    }
+
+    pub async fn fs_hardlink_async(&self, target: &Path) -> io::Result<&Self> {
+        tokio::fs::hard_link(target.to_path_buf(), self.to_path_buf()).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_link_count_async(&self) -> io::Result<u64> {
+        let path = self.clone();
+
+        blocking(move || path.fs_link_count()).await
+    }
+
+    pub async fn fs_lock_exclusive_async(&self) -> io::Result<FileLock> {
+        let path = self.clone();
+
+        blocking(move || path.fs_lock_exclusive()).await
+    }
+
+    pub async fn fs_lock_shared_async(&self) -> io::Result<FileLock> {
+        let path = self.clone();
+
+        blocking(move || path.fs_lock_shared()).await
+    }
+
+    pub async fn fs_try_lock_exclusive_async(&self) -> io::Result<Option<FileLock>> {
+        let path = self.clone();
+
+        blocking(move || path.fs_try_lock_exclusive()).await
+    }
+
+    pub async fn fs_try_lock_shared_async(&self) -> io::Result<Option<FileLock>> {
+        let path = self.clone();
+
+        blocking(move || path.fs_try_lock_shared()).await
+    }
+
+    pub async fn fs_lock_with_async(&self, options: &LockOptions) -> io::Result<FileLock> {
+        let path = self.clone();
+        let options = options.clone();
+
+        blocking(move || path.fs_lock_with(&options)).await
+    }
+
+    pub async fn fs_rename_async(&self, new_path: &Path) -> io::Result<&Self> {
+        tokio::fs::rename(self.to_path_buf(), new_path.to_path_buf()).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_rm_file_async(&self) -> io::Result<&Self> {
+        tokio::fs::remove_file(self.to_path_buf()).await?;
+        Ok(self)
+    }
+
+    pub async fn fs_rm_async(&self) -> io::Result<&Self> {
+        match self.fs_is_dir_async().await {
+            true => tokio::fs::remove_dir_all(self.to_path_buf()).await,
+            false => tokio::fs::remove_file(self.to_path_buf()).await,
+        }?;
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    #[tokio::test]
+    async fn test_async_read_write() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.with_join_str("nested/file.txt");
+
+        assert!(!file.fs_exists_async().await);
+
+        file.fs_create_parent_async().await.unwrap();
+        file.fs_write_text_async("hello").await.unwrap();
+
+        assert!(file.fs_is_file_async().await);
+        assert!(dir.with_join_str("nested").fs_is_dir_async().await);
+        assert_eq!(file.fs_read_text_async().await.unwrap(), "hello");
+        assert_eq!(file.fs_read_async().await.unwrap(), b"hello");
+        assert_eq!(file.fs_metadata_async().await.unwrap().len(), 5);
+
+        let renamed = dir.with_join_str("nested/renamed.txt");
+        file.fs_rename_async(&renamed).await.unwrap();
+
+        assert_eq!(dir.with_join_str("nested").fs_read_dir_entries_sorted_async().await.unwrap().into_iter().map(|(path, _)| path).collect::<Vec<_>>(), vec![renamed.clone()]);
+        assert_eq!(dir.with_join_str("nested").fs_read_dir_names_async().await.unwrap(), vec!["renamed.txt"]);
+
+        dir.with_join_str("nested").fs_rm_async().await.unwrap();
+        assert!(!renamed.fs_exists_async().await);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_async_change_expect() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        let outcome = file.fs_change_async("hello", fs::Permissions::from_mode(0o644)).await.unwrap();
+        assert_eq!(outcome, ChangeOutcome::Created {bytes_written: 5});
+
+        let outcome = file.fs_change_atomic_async("hello", fs::Permissions::from_mode(0o644)).await.unwrap();
+        assert_eq!(outcome, ChangeOutcome::Unchanged);
+
+        file.fs_expect_async("hello", fs::Permissions::from_mode(0o644)).await.unwrap();
+
+        let err = file.fs_expect_async("world", fs::Permissions::from_mode(0o644)).await.unwrap_err();
+        assert!(matches!(err, ImmutableErr::ContentMismatch {..}));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_async_links() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.with_join_str("file");
+        file.fs_write_async("data").await.unwrap();
+
+        let link = dir.with_join_str("link");
+        link.fs_symlink_async(&Path::from("file")).await.unwrap();
+
+        assert!(link.fs_is_symlink_async().await);
+        assert_eq!(link.fs_read_link_async().await.unwrap(), Path::from("file"));
+
+        let copy = dir.with_join_str("copy");
+        file.fs_copy_async(&copy, &CopyOptions::default()).await.unwrap();
+        assert_eq!(copy.fs_read_text_async().await.unwrap(), "data");
+
+        let relative = dir.with_join_str("nested/relative");
+        relative.fs_create_parent_async().await.unwrap();
+        relative.fs_symlink_relative_async(&file).await.unwrap();
+        assert_eq!(relative.fs_read_link_async().await.unwrap(), Path::from("../file"));
+
+        let err = Path::from("rel/link").fs_symlink_relative_async(&Path::from("rel/target")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        dir.with_join_str("hardlink").fs_hardlink_async(&file).await.unwrap();
+        assert_eq!(file.fs_link_count_async().await.unwrap(), 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_async_streaming() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        let outcome = file.fs_change_from_reader_async(io::Cursor::new(b"hello".to_vec()), fs::Permissions::from_mode(0o644)).await.unwrap();
+        assert_eq!(outcome, ChangeOutcome::Created {bytes_written: 5});
+
+        file.fs_expect_from_reader_async(io::Cursor::new(b"hello".to_vec()), fs::Permissions::from_mode(0o644)).await.unwrap();
+        assert_eq!(file.fs_read_text_with_size_async(5).await.unwrap(), "hello");
+        assert_eq!(file.fs_read_prealloc_async().await.unwrap(), b"hello");
+
+        let err = file.fs_expect_with_diff_async("world", fs::Permissions::from_mode(0o644)).await.unwrap_err();
+        assert!(matches!(err, ImmutableErr::ContentMismatch {..}));
+
+        let lock = dir.with_join_str("lock").fs_lock_exclusive_async().await.unwrap();
+        assert!(dir.with_join_str("lock").fs_try_lock_shared_async().await.unwrap().is_none());
+        drop(lock);
+        assert!(dir.with_join_str("lock").fs_try_lock_shared_async().await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,513 @@
+use std::fmt::{Display, Formatter};
+use std::{fs, io};
+
+use crate::{permissions_eq, ImmutableErr, OkMissing, Path, ToArcaPath};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangesetMode {
+    /// Perform the operations that need to be performed.
+    Apply,
+
+    /// Don't touch the disk, and fail if any operation would need to be
+    /// performed.
+    Check,
+
+    /// Don't touch the disk, only report the operations that would be
+    /// performed.
+    DryRun,
+}
+
+#[derive(Debug, Clone)]
+pub enum Operation {
+    Write {
+        path: Path,
+        data: Vec<u8>,
+        permissions: fs::Permissions,
+    },
+
+    SetPermissions {
+        path: Path,
+        permissions: fs::Permissions,
+    },
+
+    Remove {
+        path: Path,
+    },
+
+    CreateDir {
+        path: Path,
+    },
+
+    Symlink {
+        path: Path,
+        target: Path,
+    },
+}
+
+impl Operation {
+    pub fn path(&self) -> &Path {
+        match self {
+            Operation::Write {path, ..} => path,
+            Operation::SetPermissions {path, ..} => path,
+            Operation::Remove {path} => path,
+            Operation::CreateDir {path} => path,
+            Operation::Symlink {path, ..} => path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    CreateFile,
+    UpdateContent,
+    UpdatePermissions,
+    Remove,
+    CreateDir,
+    CreateSymlink,
+    UpdateSymlink,
+}
+
+#[derive(Debug)]
+pub struct Change {
+    pub path: Path,
+    pub kind: ChangeKind,
+
+    /// Details about why a file write was needed, as reported by `fs_expect`.
+    pub reason: Option<ImmutableErr>,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let label = match self.kind {
+            ChangeKind::CreateFile => "create file",
+            ChangeKind::UpdateContent => "update content",
+            ChangeKind::UpdatePermissions => "update permissions",
+            ChangeKind::Remove => "remove",
+            ChangeKind::CreateDir => "create directory",
+            ChangeKind::CreateSymlink => "create symlink",
+            ChangeKind::UpdateSymlink => "update symlink",
+        };
+
+        write!(f, "{} {}", label, self.path)
+    }
+}
+
+/// List of the changes that were performed (in `Apply` mode) or that are
+/// pending (in `Check` and `DryRun` modes).
+#[derive(Debug)]
+pub struct ChangesetReport {
+    pub mode: ChangesetMode,
+    pub changes: Vec<Change>,
+}
+
+impl ChangesetReport {
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Change> {
+        self.changes.iter()
+    }
+}
+
+impl Display for ChangesetReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ChangesetErr {
+    /// Returned in `Check` mode when some operations would need to be
+    /// performed; the report lists them.
+    Immutable(ChangesetReport),
+
+    Io(io::Error),
+}
+
+impl Display for ChangesetErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangesetErr::Immutable(report) => write!(f, "{} changes would be performed:\n{}", report.len(), report),
+            ChangesetErr::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ChangesetErr {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChangesetErr::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ChangesetErr {
+    fn from(err: io::Error) -> Self {
+        ChangesetErr::Io(err)
+    }
+}
+
+/// Records a set of filesystem operations so that they can be either applied,
+/// checked, or simulated, without callers having to pick between `fs_change`
+/// and `fs_expect` at each call site.
+#[derive(Debug, Default, Clone)]
+pub struct Changeset {
+    operations: Vec<Operation>,
+}
+
+impl Changeset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    pub fn write<T: Into<Vec<u8>>>(&mut self, path: Path, data: T, permissions: fs::Permissions) -> &mut Self {
+        self.operations.push(Operation::Write {path, data: data.into(), permissions});
+        self
+    }
+
+    pub fn set_permissions(&mut self, path: Path, permissions: fs::Permissions) -> &mut Self {
+        self.operations.push(Operation::SetPermissions {path, permissions});
+        self
+    }
+
+    pub fn remove(&mut self, path: Path) -> &mut Self {
+        self.operations.push(Operation::Remove {path});
+        self
+    }
+
+    pub fn create_dir(&mut self, path: Path) -> &mut Self {
+        self.operations.push(Operation::CreateDir {path});
+        self
+    }
+
+    /// Records a symlink at `path` pointing to `target`. Whatever exists at
+    /// `path` will be replaced if it isn't already such a symlink.
+    pub fn symlink(&mut self, path: Path, target: Path) -> &mut Self {
+        self.operations.push(Operation::Symlink {path, target});
+        self
+    }
+
+    pub fn run(&self, mode: ChangesetMode) -> Result<ChangesetReport, ChangesetErr> {
+        let mut changes = Vec::new();
+
+        for operation in &self.operations {
+            if let Some(change) = plan(operation)? {
+                if mode == ChangesetMode::Apply {
+                    apply(operation)?;
+                }
+
+                changes.push(change);
+            }
+        }
+
+        let report = ChangesetReport {
+            mode,
+            changes,
+        };
+
+        if mode == ChangesetMode::Check && !report.is_empty() {
+            return Err(ChangesetErr::Immutable(report));
+        }
+
+        Ok(report)
+    }
+}
+
+fn plan(operation: &Operation) -> io::Result<Option<Change>> {
+    let path = operation.path().clone();
+
+    let kind = match operation {
+        Operation::Write {path, ..} if is_real_dir(path)? => {
+            Some(ChangeKind::CreateFile)
+        },
+
+        Operation::Write {path, data, permissions} => {
+            match path.fs_expect(data, permissions.clone()) {
+                Ok(_) => None,
+                Err(ImmutableErr::Io(err)) if err.kind() == io::ErrorKind::NotADirectory => Some(ChangeKind::CreateFile),
+                Err(ImmutableErr::Io(err)) => return Err(err),
+
+                Err(reason) => {
+                    let kind = match reason {
+                        ImmutableErr::Missing {..} => ChangeKind::CreateFile,
+                        ImmutableErr::ContentMismatch {..} => ChangeKind::UpdateContent,
+                        ImmutableErr::DigestMismatch {..} => ChangeKind::UpdateContent,
+                        _ => ChangeKind::UpdatePermissions,
+                    };
+
+                    return Ok(Some(Change {
+                        path: path.clone(),
+                        kind,
+                        reason: Some(reason),
+                    }));
+                },
+            }
+        },
+
+        Operation::SetPermissions {path, permissions} => {
+            let current = fs::metadata(path.to_path_buf())
+                .ok_missing()
+                .or_else(not_a_directory_as_missing)?;
+
+            match current {
+                Some(metadata) if permissions_eq(&metadata.permissions(), permissions) => None,
+                _ => Some(ChangeKind::UpdatePermissions),
+            }
+        },
+
+        Operation::Remove {path} => {
+            symlink_metadata(path)?
+                .map(|_| ChangeKind::Remove)
+        },
+
+        Operation::CreateDir {path} => {
+            match path.fs_is_dir() {
+                true => None,
+                false => Some(ChangeKind::CreateDir),
+            }
+        },
+
+        Operation::Symlink {path, target} => {
+            match symlink_metadata(path)? {
+                None => Some(ChangeKind::CreateSymlink),
+                Some(metadata) if metadata.file_type().is_symlink() && fs::read_link(path.to_path_buf())?.to_arca() == *target => None,
+                Some(_) => Some(ChangeKind::UpdateSymlink),
+            }
+        },
+    };
+
+    Ok(kind.map(|kind| Change {
+        path,
+        kind,
+        reason: None,
+    }))
+}
+
+fn apply(operation: &Operation) -> io::Result<()> {
+    match operation {
+        Operation::Write {path, data, permissions} => {
+            remove_file_conflicts(path)?;
+            path.fs_change(data, permissions.clone())?;
+        },
+
+        Operation::SetPermissions {path, permissions} => {
+            path.fs_set_permissions(permissions.clone())?;
+        },
+
+        Operation::Remove {path} => {
+            remove(path)?;
+        },
+
+        Operation::CreateDir {path} => {
+            path.fs_create_dir_all()?;
+        },
+
+        Operation::Symlink {path, target} => {
+            if symlink_metadata(path)?.is_some() {
+                remove(path)?;
+            }
+
+            create_symlink(target, path)?;
+        },
+    }
+
+    Ok(())
+}
+
+// When planning, a file may be located inside a directory that's currently a
+// file but that an earlier operation will replace.
+fn not_a_directory_as_missing<T>(err: io::Error) -> io::Result<Option<T>> {
+    match err.kind() {
+        io::ErrorKind::NotADirectory => Ok(None),
+        _ => Err(err),
+    }
+}
+
+fn symlink_metadata(path: &Path) -> io::Result<Option<fs::Metadata>> {
+    fs::symlink_metadata(path.to_path_buf())
+        .ok_missing()
+        .or_else(not_a_directory_as_missing)
+}
+
+fn is_real_dir(path: &Path) -> io::Result<bool> {
+    Ok(symlink_metadata(path)?
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false))
+}
+
+// Like `fs_sync_dir`, directories in the way of a file are removed, and
+// files in the way of its parent directories are replaced.
+fn remove_file_conflicts(path: &Path) -> io::Result<()> {
+    if is_real_dir(path)? {
+        return remove(path);
+    }
+
+    let path_buf = path.to_path_buf();
+    let parents = path_buf.ancestors().skip(1).collect::<Vec<_>>();
+
+    for parent in parents.iter().rev() {
+        match fs::metadata(parent) {
+            Ok(metadata) if metadata.is_dir() => {},
+
+            Ok(_) => {
+                fs::remove_file(parent)?;
+                return fs::create_dir_all(parents[0]);
+            },
+
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn remove(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path.to_path_buf())?.is_dir() {
+        true => fs::remove_dir_all(path.to_path_buf()),
+        false => fs::remove_file(path.to_path_buf()),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn create_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target.to_path_buf(), path.to_path_buf())
+}
+
+#[cfg(windows)]
+pub(crate) fn create_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    let absolute_target = match (target.is_absolute(), path.dirname()) {
+        (false, Some(dirname)) => dirname.with_join(target),
+        _ => target.clone(),
+    };
+
+    match absolute_target.fs_is_dir() {
+        true => std::os::windows::fs::symlink_dir(target.to_path_buf(), path.to_path_buf()),
+        false => std::os::windows::fs::symlink_file(target.to_path_buf(), path.to_path_buf()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn make_changeset(dir: &Path) -> Changeset {
+        let mut changeset = Changeset::new();
+
+        changeset
+            .create_dir(dir.with_join_str("sub"))
+            .write(dir.with_join_str("sub/file.txt"), "hello", fs::Permissions::from_mode(0o644))
+            .write(dir.with_join_str("bin.sh"), "#!/bin/sh", fs::Permissions::from_mode(0o755))
+            .remove(dir.with_join_str("stale.txt"));
+
+        changeset
+    }
+
+    #[test]
+    fn test_changeset_apply() {
+        let dir = Path::temp_dir().unwrap();
+        dir.with_join_str("stale.txt").fs_write("stale").unwrap();
+
+        let report = make_changeset(&dir).run(ChangesetMode::Apply).unwrap();
+
+        let kinds = report.iter()
+            .map(|change| change.kind)
+            .collect::<Vec<_>>();
+
+        assert_eq!(kinds, vec![ChangeKind::CreateDir, ChangeKind::CreateFile, ChangeKind::CreateFile, ChangeKind::Remove]);
+
+        assert_eq!(dir.with_join_str("sub/file.txt").fs_read_text().unwrap(), "hello");
+        assert!(!dir.with_join_str("stale.txt").fs_exists());
+
+        let report = make_changeset(&dir).run(ChangesetMode::Apply).unwrap();
+        assert!(report.is_empty());
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_changeset_apply_conflicts() {
+        let dir = Path::temp_dir().unwrap();
+        dir.with_join_str("file.txt/nested").fs_create_dir_all().unwrap();
+        dir.with_join_str("parent").fs_write("not a directory").unwrap();
+
+        let mut changeset = Changeset::new();
+
+        changeset
+            .write(dir.with_join_str("file.txt"), "hello", fs::Permissions::from_mode(0o644))
+            .write(dir.with_join_str("parent/file.txt"), "world", fs::Permissions::from_mode(0o644));
+
+        let report = changeset.run(ChangesetMode::Apply).unwrap();
+
+        let kinds = report.iter()
+            .map(|change| change.kind)
+            .collect::<Vec<_>>();
+
+        assert_eq!(kinds, vec![ChangeKind::CreateFile, ChangeKind::CreateFile]);
+
+        assert_eq!(dir.with_join_str("file.txt").fs_read_text().unwrap(), "hello");
+        assert_eq!(dir.with_join_str("parent/file.txt").fs_read_text().unwrap(), "world");
+
+        assert!(changeset.run(ChangesetMode::Check).unwrap().is_empty());
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_changeset_check() {
+        let dir = Path::temp_dir().unwrap();
+        make_changeset(&dir).run(ChangesetMode::Apply).unwrap();
+
+        assert!(make_changeset(&dir).run(ChangesetMode::Check).unwrap().is_empty());
+
+        dir.with_join_str("sub/file.txt").fs_write("modified").unwrap();
+        dir.with_join_str("bin.sh").fs_set_permissions(fs::Permissions::from_mode(0o644)).unwrap();
+
+        match make_changeset(&dir).run(ChangesetMode::Check) {
+            Err(ChangesetErr::Immutable(report)) => {
+                let kinds = report.iter()
+                    .map(|change| (change.path.clone(), change.kind))
+                    .collect::<Vec<_>>();
+
+                assert_eq!(kinds, vec![
+                    (dir.with_join_str("sub/file.txt"), ChangeKind::UpdateContent),
+                    (dir.with_join_str("bin.sh"), ChangeKind::UpdatePermissions),
+                ]);
+            },
+
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        assert_eq!(dir.with_join_str("sub/file.txt").fs_read_text().unwrap(), "modified");
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_changeset_dry_run() {
+        let dir = Path::temp_dir().unwrap();
+
+        let report = make_changeset(&dir).run(ChangesetMode::DryRun).unwrap();
+        assert_eq!(report.len(), 3);
+
+        assert!(!dir.with_join_str("sub").fs_exists());
+
+        dir.fs_rm().unwrap();
+    }
+}
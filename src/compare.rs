@@ -0,0 +1,135 @@
+use std::io::{self, Read};
+
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Incremental hasher used to compare files against precomputed digests
+/// without having to load them in memory.
+pub trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self) -> Vec<u8>;
+}
+
+/// Location of the first difference between two streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StreamDiff {
+    pub offset: u64,
+
+    /// Line (1-based) of the first differing byte in the expected stream.
+    pub line: usize,
+}
+
+/// Reads until the buffer is full or the end of the stream is reached.
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Compares two streams chunk by chunk, returning the location of the first
+/// difference (if any).
+pub(crate) fn compare_streams<E: Read, A: Read>(expected: &mut E, actual: &mut A) -> io::Result<Option<StreamDiff>> {
+    let mut expected_buf = vec![0; CHUNK_SIZE];
+    let mut actual_buf = vec![0; CHUNK_SIZE];
+
+    let mut offset = 0;
+    let mut line = 1;
+
+    loop {
+        let expected_len = read_full(expected, &mut expected_buf)?;
+        let actual_len = read_full(actual, &mut actual_buf)?;
+
+        let common_len = expected_buf[..expected_len].iter()
+            .zip(actual_buf[..actual_len].iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        line += expected_buf[..common_len].iter()
+            .filter(|c| **c == b'\n')
+            .count();
+
+        offset += common_len as u64;
+
+        if common_len < expected_len || common_len < actual_len {
+            return Ok(Some(StreamDiff {offset, line}));
+        }
+
+        if expected_len == 0 {
+            return Ok(None);
+        }
+    }
+}
+
+pub(crate) fn hash_stream<R: Read, H: ContentHasher>(reader: &mut R, mut hasher: H) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; CHUNK_SIZE];
+
+    loop {
+        let len = read_full(reader, &mut buf)?;
+
+        if len == 0 {
+            return Ok(hasher.finalize());
+        }
+
+        hasher.update(&buf[..len]);
+    }
+}
+
+/// Reader wrapper keeping track of how many bytes went through it, and of
+/// whether the end of the stream was reached.
+pub(crate) struct CountingReader<R> {
+    pub inner: R,
+    pub count: u64,
+    pub eof: bool,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        self.eof |= n == 0 && !buf.is_empty();
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_streams_identical() {
+        let data = vec![42; CHUNK_SIZE * 2 + 10];
+        assert_eq!(compare_streams(&mut data.as_slice(), &mut data.as_slice()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_compare_streams_difference() {
+        let expected = b"hello\nworld\n";
+        let actual = b"hello\nthere\n";
+
+        assert_eq!(compare_streams(&mut &expected[..], &mut &actual[..]).unwrap(), Some(StreamDiff {offset: 6, line: 2}));
+    }
+
+    #[test]
+    fn test_compare_streams_prefix() {
+        assert_eq!(compare_streams(&mut &b"hello"[..], &mut &b"hello world"[..]).unwrap(), Some(StreamDiff {offset: 5, line: 1}));
+        assert_eq!(compare_streams(&mut &b"hello world"[..], &mut &b"hello"[..]).unwrap(), Some(StreamDiff {offset: 5, line: 1}));
+    }
+
+    #[test]
+    fn test_compare_streams_across_chunks() {
+        let expected = vec![0; CHUNK_SIZE * 2];
+
+        let mut actual = expected.clone();
+        actual[CHUNK_SIZE + 5] = 1;
+
+        assert_eq!(compare_streams(&mut expected.as_slice(), &mut actual.as_slice()).unwrap(), Some(StreamDiff {offset: CHUNK_SIZE as u64 + 5, line: 1}));
+    }
+}
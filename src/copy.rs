@@ -0,0 +1,398 @@
+use std::{fs, io};
+
+use crate::changeset::{create_symlink, remove};
+use crate::{OkMissing, Path, ToArcaPath};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CopyStrategy {
+    #[default]
+    Copy,
+
+    Hardlink,
+
+    /// Share the data blocks with the source when the filesystem supports it
+    /// (FICLONE on Linux), and fall back to a regular copy otherwise.
+    Reflink,
+
+    /// Create symlinks pointing to the source files. Relative sources are
+    /// resolved against the current directory first.
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Fail with `AlreadyExists` when the destination exists.
+    #[default]
+    Error,
+
+    /// Leave the existing destination untouched.
+    Skip,
+
+    /// Remove the existing destination before copying.
+    Replace,
+}
+
+/// Options for `Path::fs_copy`. Existing directories are always merged with
+/// the copied ones; the overwrite policy only applies to other entries.
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    pub strategy: CopyStrategy,
+    pub overwrite: OverwritePolicy,
+
+    /// Apply the permissions of the source to the copied files and
+    /// directories. Hardlinks and symlinks are unaffected.
+    pub preserve_permissions: bool,
+
+    /// Apply the modification time of the source to the copied files and
+    /// directories. Hardlinks and symlinks are unaffected.
+    pub preserve_mtime: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        CopyOptions {
+            strategy: CopyStrategy::Copy,
+            overwrite: OverwritePolicy::Error,
+            preserve_permissions: true,
+            preserve_mtime: false,
+        }
+    }
+}
+
+pub(crate) fn copy(source: &Path, destination: &Path, options: &CopyOptions) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(source.to_path_buf())?;
+
+    // Copied as-is, symlinks are only resolved up to their parent
+    let canonical_source = match metadata.file_type().is_symlink() {
+        true => canonicalize_parent(source)?,
+        false => fs::canonicalize(source.to_path_buf())?,
+    };
+
+    // Copying a directory inside itself would keep finding the entries it
+    // just created
+    if canonicalize_parent(destination)?.starts_with(&canonical_source) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Can't copy {} into itself ({})", source, destination)));
+    }
+
+    copy_entry(source, destination, &metadata, options)
+}
+
+/// Canonicalizes the closest existing ancestor of the path, and appends the
+/// remaining components as-is. The last component is never resolved, since
+/// an existing symlink there would be replaced rather than followed.
+fn canonicalize_parent(path: &Path) -> io::Result<std::path::PathBuf> {
+    let absolute = std::path::absolute(path.to_path_buf())?;
+
+    let mut ancestor = absolute.as_path();
+    let mut rest = Vec::new();
+
+    if let (Some(name), Some(parent)) = (ancestor.file_name(), ancestor.parent()) {
+        rest.push(name);
+        ancestor = parent;
+    }
+
+    loop {
+        match fs::canonicalize(ancestor) {
+            Ok(canonical) => {
+                return Ok(rest.into_iter().rev().fold(canonical, |path, name| path.join(name)));
+            },
+
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let (Some(name), Some(parent)) = (ancestor.file_name(), ancestor.parent()) else {
+                    return Err(err);
+                };
+
+                rest.push(name);
+                ancestor = parent;
+            },
+
+            Err(err) => {
+                return Err(err);
+            },
+        }
+    }
+}
+
+fn copy_entry(source: &Path, destination: &Path, metadata: &fs::Metadata, options: &CopyOptions) -> io::Result<()> {
+    let file_type = metadata.file_type();
+
+    let mut merge = false;
+
+    if let Some(existing) = fs::symlink_metadata(destination.to_path_buf()).ok_missing()? {
+        if file_type.is_dir() && existing.is_dir() {
+            merge = true;
+        } else {
+            match options.overwrite {
+                OverwritePolicy::Error => {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", destination)));
+                },
+
+                OverwritePolicy::Skip => {
+                    return Ok(());
+                },
+
+                OverwritePolicy::Replace => {
+                    remove(destination)?;
+                },
+            }
+        }
+    }
+
+    if file_type.is_dir() {
+        copy_dir(source, destination, metadata, merge, options)
+    } else if file_type.is_symlink() {
+        create_symlink(&fs::read_link(source.to_path_buf())?.to_arca(), destination)
+    } else {
+        copy_file(source, destination, metadata, options)
+    }
+}
+
+fn copy_dir(source: &Path, destination: &Path, metadata: &fs::Metadata, merge: bool, options: &CopyOptions) -> io::Result<()> {
+    if !merge {
+        fs::create_dir(destination.to_path_buf())?;
+    }
+
+    for (child, _) in source.fs_read_dir_entries()? {
+        let child_metadata = fs::symlink_metadata(child.to_path_buf())?;
+        let child_destination = destination.with_entry_name(child.basename().unwrap());
+
+        copy_entry(&child, &child_destination, &child_metadata, options)?;
+    }
+
+    // Applied last, so that read-only directories can still be populated
+    if options.preserve_permissions {
+        fs::set_permissions(destination.to_path_buf(), metadata.permissions())?;
+    }
+
+    if options.preserve_mtime {
+        open_dir(destination)?
+            .set_modified(metadata.modified()?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn open_dir(path: &Path) -> io::Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+
+    // Directories can only be opened with the backup semantics flag
+    fs::OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path.to_path_buf())
+}
+
+#[cfg(not(windows))]
+fn open_dir(path: &Path) -> io::Result<fs::File> {
+    fs::File::open(path.to_path_buf())
+}
+
+fn copy_file(source: &Path, destination: &Path, metadata: &fs::Metadata, options: &CopyOptions) -> io::Result<()> {
+    match options.strategy {
+        CopyStrategy::Hardlink => {
+            return fs::hard_link(source.to_path_buf(), destination.to_path_buf());
+        },
+
+        CopyStrategy::Symlink => {
+            let target = match source.is_absolute() {
+                true => source.clone(),
+                false => Path::current_dir()?.with_join(source),
+            };
+
+            return create_symlink(&target, destination);
+        },
+
+        CopyStrategy::Copy | CopyStrategy::Reflink => {},
+    }
+
+    let mut reader = fs::File::open(source.to_path_buf())?;
+
+    let mut writer = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(destination.to_path_buf())?;
+
+    if options.strategy != CopyStrategy::Reflink || !reflink(&reader, &writer)? {
+        io::copy(&mut reader, &mut writer)?;
+    }
+
+    if options.preserve_permissions {
+        writer.set_permissions(metadata.permissions())?;
+    }
+
+    if options.preserve_mtime {
+        writer.set_modified(metadata.modified()?)?;
+    }
+
+    Ok(())
+}
+
+/// Returns false if the filesystem doesn't support cloning files.
+#[cfg(target_os = "linux")]
+fn reflink(source: &fs::File, destination: &fs::File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let res = unsafe {
+        libc::ioctl(destination.as_raw_fd(), libc::FICLONE, source.as_raw_fd())
+    };
+
+    if res == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP | libc::EXDEV | libc::EINVAL | libc::ENOTTY | libc::EPERM) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &fs::File, _destination: &fs::File) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    use super::*;
+
+    fn make_source(dir: &Path) -> Path {
+        let source = dir.with_join_str("source");
+
+        source.with_join_str("nested").fs_create_dir_all().unwrap();
+        source.with_join_str("file").fs_write("file").unwrap();
+        source.with_join_str("nested/script").fs_write("#!/bin/sh").unwrap();
+        source.with_join_str("nested/script").fs_set_permissions(fs::Permissions::from_mode(0o755)).unwrap();
+
+        std::os::unix::fs::symlink("../file", source.with_join_str("nested/link").to_path_buf()).unwrap();
+
+        source
+    }
+
+    #[test]
+    fn test_copy_tree() {
+        let dir = Path::temp_dir().unwrap();
+        let source = make_source(&dir);
+
+        for strategy in [CopyStrategy::Copy, CopyStrategy::Reflink] {
+            let destination = dir.with_join_str("destination");
+
+            source.fs_copy(&destination, &CopyOptions {strategy, ..CopyOptions::default()}).unwrap();
+
+            assert_eq!(destination.with_join_str("file").fs_read_text().unwrap(), "file");
+            assert_eq!(destination.with_join_str("nested/script").fs_read_text().unwrap(), "#!/bin/sh");
+            assert_eq!(destination.with_join_str("nested/script").fs_metadata().unwrap().mode() & 0o777, 0o755);
+            assert_eq!(fs::read_link(destination.with_join_str("nested/link").to_path_buf()).unwrap().to_str(), Some("../file"));
+
+            destination.fs_rm().unwrap();
+        }
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_copy_links() {
+        let dir = Path::temp_dir().unwrap();
+        let source = make_source(&dir);
+
+        let hardlinks = dir.with_join_str("hardlinks");
+        source.fs_copy(&hardlinks, &CopyOptions {strategy: CopyStrategy::Hardlink, ..CopyOptions::default()}).unwrap();
+
+        let source_ino = source.with_join_str("file").fs_metadata().unwrap().ino();
+        assert_eq!(hardlinks.with_join_str("file").fs_metadata().unwrap().ino(), source_ino);
+
+        let symlinks = dir.with_join_str("symlinks");
+        source.fs_copy(&symlinks, &CopyOptions {strategy: CopyStrategy::Symlink, ..CopyOptions::default()}).unwrap();
+
+        assert_eq!(fs::read_link(symlinks.with_join_str("file").to_path_buf()).unwrap().to_arca(), source.with_join_str("file"));
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_copy_overwrite() {
+        let dir = Path::temp_dir().unwrap();
+        let source = make_source(&dir);
+
+        let destination = dir.with_join_str("destination");
+        destination.fs_create_dir_all().unwrap();
+        destination.with_join_str("file").fs_write("old").unwrap();
+        destination.with_join_str("extra").fs_write("extra").unwrap();
+
+        let err = source.fs_copy(&destination, &CopyOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        source.fs_copy(&destination, &CopyOptions {overwrite: OverwritePolicy::Skip, ..CopyOptions::default()}).unwrap();
+        assert_eq!(destination.with_join_str("file").fs_read_text().unwrap(), "old");
+        assert!(destination.with_join_str("nested/script").fs_exists());
+
+        source.fs_copy(&destination, &CopyOptions {overwrite: OverwritePolicy::Replace, ..CopyOptions::default()}).unwrap();
+        assert_eq!(destination.with_join_str("file").fs_read_text().unwrap(), "file");
+        assert!(destination.with_join_str("extra").fs_exists());
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_copy_mtime() {
+        let dir = Path::temp_dir().unwrap();
+        let source = dir.with_join_str("source");
+        source.fs_write("data").unwrap();
+
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        fs::File::options().write(true).open(source.to_path_buf()).unwrap().set_modified(mtime).unwrap();
+
+        let destination = dir.with_join_str("destination");
+        source.fs_copy(&destination, &CopyOptions {preserve_mtime: true, ..CopyOptions::default()}).unwrap();
+
+        assert_eq!(destination.fs_metadata().unwrap().modified().unwrap(), mtime);
+
+        let source_dir = dir.with_join_str("source_dir");
+        source_dir.fs_create_dir().unwrap();
+        fs::File::open(source_dir.to_path_buf()).unwrap().set_modified(mtime).unwrap();
+
+        let destination_dir = dir.with_join_str("destination_dir");
+        source_dir.fs_copy(&destination_dir, &CopyOptions {preserve_mtime: true, ..CopyOptions::default()}).unwrap();
+
+        assert_eq!(destination_dir.fs_metadata().unwrap().modified().unwrap(), mtime);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_copy_into_itself() {
+        let dir = Path::temp_dir().unwrap();
+        let source = make_source(&dir);
+
+        let err = source.fs_copy(&source.with_join_str("sub"), &CopyOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!source.with_join_str("sub").fs_exists());
+
+        let err = source.fs_copy(&source.with_join_str("missing/deeper"), &CopyOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let err = source.fs_copy(&source, &CopyOptions {overwrite: OverwritePolicy::Replace, ..CopyOptions::default()}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(source.with_join_str("file").fs_exists());
+
+        // Going through a symlink to the source doesn't hide it
+        let alias = dir.with_join_str("alias");
+        alias.fs_symlink(&source).unwrap();
+
+        let err = source.fs_copy(&alias.with_join_str("sub"), &CopyOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // A sibling sharing the prefix of the name is fine
+        source.fs_copy(&dir.with_join_str("source2"), &CopyOptions::default()).unwrap();
+        assert!(dir.with_join_str("source2/file").fs_exists());
+
+        dir.fs_rm().unwrap();
+    }
+}
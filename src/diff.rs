@@ -0,0 +1,197 @@
+const CONTEXT_LINES: usize = 3;
+
+// Above this number of cells we don't try to find the smallest diff, and
+// instead report the whole differing region as removed then added.
+const MAX_LCS_CELLS: usize = 1 << 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    let prefix_len = old.iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let suffix_len = old[prefix_len..].iter().rev()
+        .zip(new[prefix_len..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix_len..old.len() - suffix_len];
+    let new_mid = &new[prefix_len..new.len() - suffix_len];
+
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    ops.extend(old[..prefix_len].iter().map(|line| (Op::Equal, *line)));
+
+    if (old_mid.len() + 1).saturating_mul(new_mid.len() + 1) <= MAX_LCS_CELLS {
+        let width = new_mid.len() + 1;
+        let mut lcs = vec![0u32; (old_mid.len() + 1) * width];
+
+        for i in (0..old_mid.len()).rev() {
+            for j in (0..new_mid.len()).rev() {
+                lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+
+        while i < old_mid.len() || j < new_mid.len() {
+            if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+                ops.push((Op::Equal, old_mid[i]));
+                i += 1;
+                j += 1;
+            } else if i < old_mid.len() && (j == new_mid.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                ops.push((Op::Delete, old_mid[i]));
+                i += 1;
+            } else {
+                ops.push((Op::Insert, new_mid[j]));
+                j += 1;
+            }
+        }
+    } else {
+        ops.extend(old_mid.iter().map(|line| (Op::Delete, *line)));
+        ops.extend(new_mid.iter().map(|line| (Op::Insert, *line)));
+    }
+
+    ops.extend(old[old.len() - suffix_len..].iter().map(|line| (Op::Equal, *line)));
+    ops
+}
+
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Generates a unified diff between two texts, or an empty string if they're
+/// identical.
+pub(crate) fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    let changes = ops.iter()
+        .enumerate()
+        .filter(|(_, (op, _))| *op != Op::Equal)
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+
+    for index in changes {
+        let start = index.saturating_sub(CONTEXT_LINES);
+        let end = (index + CONTEXT_LINES + 1).min(ops.len());
+
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut output = format!("--- {}\n+++ {}\n", old_label, new_label);
+
+    for (start, end) in hunks {
+        let old_start = ops[..start].iter().filter(|(op, _)| *op != Op::Insert).count();
+        let new_start = ops[..start].iter().filter(|(op, _)| *op != Op::Delete).count();
+
+        let old_count = ops[start..end].iter().filter(|(op, _)| *op != Op::Insert).count();
+        let new_count = ops[start..end].iter().filter(|(op, _)| *op != Op::Delete).count();
+
+        output.push_str(&format!("@@ -{} +{} @@\n", hunk_range(old_start, old_count), hunk_range(new_start, new_count)));
+
+        for (op, line) in &ops[start..end] {
+            output.push(match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            });
+
+            output.push_str(line);
+
+            if !line.ends_with('\n') {
+                output.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff_identical() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "old", "new"), "");
+    }
+
+    #[test]
+    fn test_unified_diff_change() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\nh\n";
+
+        assert_eq!(unified_diff(old, new, "old", "new"), concat!(
+            "--- old\n",
+            "+++ new\n",
+            "@@ -2,7 +2,7 @@\n",
+            " b\n",
+            " c\n",
+            " d\n",
+            "-e\n",
+            "+E\n",
+            " f\n",
+            " g\n",
+            " h\n",
+        ));
+    }
+
+    #[test]
+    fn test_unified_diff_separate_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n";
+
+        assert_eq!(unified_diff(old, new, "old", "new"), concat!(
+            "--- old\n",
+            "+++ new\n",
+            "@@ -1,3 +1,4 @@\n",
+            "+0\n",
+            " 1\n",
+            " 2\n",
+            " 3\n",
+            "@@ -9,4 +10,3 @@\n",
+            " 9\n",
+            " 10\n",
+            " 11\n",
+            "-12\n",
+        ));
+    }
+
+    #[test]
+    fn test_unified_diff_missing_newline() {
+        assert_eq!(unified_diff("a\n", "a", "old", "new"), concat!(
+            "--- old\n",
+            "+++ new\n",
+            "@@ -1 +1 @@\n",
+            "-a\n",
+            "+a\n",
+            "\\ No newline at end of file\n",
+        ));
+    }
+}
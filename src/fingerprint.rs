@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::time::UNIX_EPOCH;
+use std::{fs, io};
+
+use crate::{ContentHasher, OkMissing, Path};
+
+const MAGIC: &[u8; 8] = b"ARCAFP01";
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// Cheap summary of a file's state. Two fingerprints with the same metadata
+/// are assumed to describe the same content.
+#[derive(Debug, Clone, PartialEq, Eq, bincode_derive::Encode, bincode_derive::Decode)]
+pub struct Fingerprint {
+    pub size: u64,
+
+    /// Modification time, in nanoseconds since the Unix epoch.
+    pub mtime: u128,
+
+    /// Always 0 on platforms without inodes.
+    pub inode: u64,
+
+    /// Content hash, only computed when requested through `FingerprintStore::hash`.
+    pub hash: Option<Vec<u8>>,
+}
+
+impl Fingerprint {
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let mtime = metadata.modified().ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .map(|mtime| mtime.as_nanos())
+            .unwrap_or(0);
+
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Fingerprint {
+            size: metadata.len(),
+            mtime,
+            inode,
+            hash: None,
+        }
+    }
+
+    pub fn same_metadata(&self, other: &Fingerprint) -> bool {
+        self.size == other.size && self.mtime == other.mtime && self.inode == other.inode
+    }
+}
+
+fn current(path: &Path) -> io::Result<Option<Fingerprint>> {
+    Ok(path.fs_metadata().ok_missing()?
+        .map(|metadata| Fingerprint::from_metadata(&metadata)))
+}
+
+/// Records file fingerprints between runs, so that callers can find out which
+/// files changed without having to read them.
+#[derive(Debug, Clone, Default)]
+pub struct FingerprintStore {
+    entries: BTreeMap<Path, Fingerprint>,
+}
+
+impl FingerprintStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a store previously written by `save`, or returns an empty store if
+    /// the file doesn't exist.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let Some(data) = fs::read(path.to_path_buf()).ok_missing()? else {
+            return Ok(Self::new());
+        };
+
+        let payload = data.strip_prefix(MAGIC.as_slice())
+            .ok_or_else(|| invalid_data(format!("{} isn't a fingerprint store", path)))?;
+
+        let (entries, _) = bincode::decode_from_slice(payload, bincode::config::standard())
+            .map_err(invalid_data)?;
+
+        Ok(FingerprintStore {entries})
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut data = MAGIC.to_vec();
+
+        bincode::encode_into_std_write(&self.entries, &mut data, bincode::config::standard())
+            .map_err(invalid_data)?;
+
+        path.fs_write_atomic(data)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Fingerprint> {
+        self.entries.get(path)
+    }
+
+    pub fn remove(&mut self, path: &Path) -> Option<Fingerprint> {
+        self.entries.remove(path)
+    }
+
+    /// Records the current state of the file, returning whether it changed
+    /// since the last time it was recorded. Missing files are forgotten.
+    pub fn update(&mut self, path: &Path) -> io::Result<bool> {
+        let Some(mut fingerprint) = current(path)? else {
+            return Ok(self.entries.remove(path).is_some());
+        };
+
+        let changed = match self.entries.get(path) {
+            Some(previous) if previous.same_metadata(&fingerprint) => {
+                fingerprint.hash = previous.hash.clone();
+                false
+            },
+
+            _ => true,
+        };
+
+        self.entries.insert(path.clone(), fingerprint);
+        Ok(changed)
+    }
+
+    /// Returns the paths whose metadata differs from what was recorded, which
+    /// were never recorded, or which were recorded but don't exist anymore.
+    pub fn changed<'a, I: IntoIterator<Item = &'a Path>>(&self, paths: I) -> io::Result<Vec<Path>> {
+        let mut changed = Vec::new();
+
+        for path in paths {
+            let is_changed = match (self.entries.get(path), current(path)?) {
+                (Some(previous), Some(fingerprint)) => !previous.same_metadata(&fingerprint),
+                (None, None) => false,
+                _ => true,
+            };
+
+            if is_changed {
+                changed.push(path.clone());
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Same as `changed`, but files whose metadata changed are rehashed when
+    /// a hash was recorded, and only reported if their content differs.
+    pub fn changed_content<'a, I, H, F>(&self, paths: I, make_hasher: F) -> io::Result<Vec<Path>>
+    where
+        I: IntoIterator<Item = &'a Path>,
+        H: ContentHasher,
+        F: Fn() -> H,
+    {
+        let mut changed = Vec::new();
+
+        for path in self.changed(paths)? {
+            let same_content = match self.entries.get(&path).and_then(|previous| previous.hash.as_ref()) {
+                Some(hash) if path.fs_exists() => path.fs_hash(make_hasher())? == *hash,
+                _ => false,
+            };
+
+            if !same_content {
+                changed.push(path);
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Returns the content hash of the file, only reading it if it changed
+    /// since the hash was last computed. The fingerprint is updated.
+    pub fn hash<H: ContentHasher>(&mut self, path: &Path, hasher: H) -> io::Result<Vec<u8>> {
+        let fingerprint = current(path)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't exist", path)))?;
+
+        if let Some(previous) = self.entries.get(path) {
+            if let (true, Some(hash)) = (previous.same_metadata(&fingerprint), &previous.hash) {
+                return Ok(hash.clone());
+            }
+        }
+
+        let hash = path.fs_hash(hasher)?;
+
+        // The file may have been modified while we were reading it
+        let after = current(path)?;
+
+        if after.as_ref().is_some_and(|after| after.same_metadata(&fingerprint)) {
+            self.entries.insert(path.clone(), Fingerprint {hash: Some(hash.clone()), ..fingerprint});
+        } else {
+            self.entries.remove(path);
+        }
+
+        Ok(hash)
+    }
+
+    pub fn iter(&self) -> std::collections::btree_map::Iter<'_, Path, Fingerprint> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::TempDir;
+
+    thread_local! {
+        static HASH_CALLS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[derive(Default)]
+    struct CountingHasher(Vec<u8>);
+
+    impl ContentHasher for CountingHasher {
+        fn update(&mut self, data: &[u8]) {
+            self.0.extend_from_slice(data);
+        }
+
+        fn finalize(self) -> Vec<u8> {
+            HASH_CALLS.with(|calls| calls.set(calls.get() + 1));
+            self.0
+        }
+    }
+
+    fn set_mtime(path: &Path, secs: u64) {
+        fs::File::options().write(true).open(path.to_path_buf()).unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint_changed() {
+        let dir = TempDir::new().unwrap();
+
+        let a = dir.with_join_str("a");
+        let b = dir.with_join_str("b");
+        let c = dir.with_join_str("c");
+
+        a.fs_write("a").unwrap();
+        b.fs_write("b").unwrap();
+
+        let mut store = FingerprintStore::new();
+        assert!(store.update(&a).unwrap());
+        assert!(store.update(&b).unwrap());
+        assert!(!store.update(&a).unwrap());
+
+        assert_eq!(store.changed([&a, &b, &c]).unwrap(), Vec::<Path>::new());
+
+        b.fs_write("bb").unwrap();
+        c.fs_write("c").unwrap();
+        a.fs_rm_file().unwrap();
+
+        assert_eq!(store.changed([&a, &b, &c]).unwrap(), vec![a.clone(), b.clone(), c.clone()]);
+    }
+
+    #[test]
+    fn test_fingerprint_lazy_hash() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.with_join_str("file");
+
+        file.fs_write("hello").unwrap();
+        set_mtime(&file, 1000);
+
+        let mut store = FingerprintStore::new();
+        let calls = HASH_CALLS.with(|calls| calls.get());
+
+        assert_eq!(store.hash(&file, CountingHasher::default()).unwrap(), b"hello");
+        assert_eq!(store.hash(&file, CountingHasher::default()).unwrap(), b"hello");
+        assert_eq!(HASH_CALLS.with(|calls| calls.get()), calls + 1);
+
+        // Only touched: the content is still the same
+        set_mtime(&file, 2000);
+        assert_eq!(store.changed([&file]).unwrap(), vec![file.clone()]);
+        assert_eq!(store.changed_content([&file], CountingHasher::default).unwrap(), Vec::<Path>::new());
+
+        file.fs_write("world").unwrap();
+        assert_eq!(store.changed_content([&file], CountingHasher::default).unwrap(), vec![file.clone()]);
+    }
+
+    #[test]
+    fn test_fingerprint_persistence() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.with_join_str("file");
+        let db = dir.with_join_str("fingerprints.bin");
+
+        file.fs_write("hello").unwrap();
+
+        let mut store = FingerprintStore::load(&db).unwrap();
+        assert!(store.is_empty());
+
+        store.hash(&file, CountingHasher::default()).unwrap();
+        store.save(&db).unwrap();
+
+        let loaded = FingerprintStore::load(&db).unwrap();
+        assert_eq!(loaded.get(&file), store.get(&file));
+        assert_eq!(loaded.get(&file).unwrap().hash.as_deref(), Some(b"hello".as_slice()));
+
+        db.fs_write("garbage").unwrap();
+        assert_eq!(FingerprintStore::load(&db).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,282 @@
+use std::sync::Arc;
+use std::{fs, io};
+
+use crate::compare::{self, ContentHasher};
+use crate::Path;
+
+type HashTreeFilter = Arc<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// Options for `Path::fs_hash_tree`.
+#[derive(Clone, Default)]
+pub struct HashTreeOptions {
+    /// Entries with one of these names are skipped, along with their content.
+    pub ignored_names: Vec<String>,
+
+    /// Called with the path of each entry relative to the root; entries for
+    /// which it returns false are skipped, along with their content.
+    pub filter: Option<HashTreeFilter>,
+}
+
+impl std::fmt::Debug for HashTreeOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HashTreeOptions")
+            .field("ignored_names", &self.ignored_names)
+            .field("filter", &self.filter.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
+
+fn relative_path(root: &Path, path: &Path) -> Path {
+    let relative = path.as_str()
+        .strip_prefix(root.as_str())
+        .unwrap_or(path.as_str())
+        .trim_start_matches('/');
+
+    Path::from(relative)
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+fn update_with_len<H: ContentHasher>(hasher: &mut H, data: &[u8]) {
+    hasher.update(&(data.len() as u64).to_le_bytes());
+    hasher.update(data);
+}
+
+/// Hashes every file and symlink of the tree, in the order of a sorted walk.
+/// Each entry contributes its kind, relative path, and content (or target),
+/// all length-prefixed so that different trees can't produce the same input.
+/// Directories only matter through their content, and symlinks aren't
+/// followed.
+pub(crate) fn hash_tree<H: ContentHasher>(root: &Path, mut hasher: H, options: &HashTreeOptions) -> io::Result<Vec<u8>> {
+    if !root.fs_is_dir() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Expected {} to be a directory", root)));
+    }
+
+    let filter_root = root.clone();
+    let filter_options = options.clone();
+
+    let walk = root.fs_walk()
+        .sorted(true)
+        .min_depth(1)
+        .filter_entry(move |path, _| {
+            // The walker also submits the root, which must never be skipped
+            if *path == filter_root {
+                return true;
+            }
+
+            let ignored = path.basename()
+                .map(|name| filter_options.ignored_names.iter().any(|ignored| ignored == name))
+                .unwrap_or(false);
+
+            !ignored && filter_options.filter.as_ref()
+                .map(|filter| filter(&relative_path(&filter_root, path)))
+                .unwrap_or(true)
+        });
+
+    for entry in walk {
+        let (path, file_type) = entry.map_err(|err| err.into_io_error())?;
+        let relative = relative_path(root, &path);
+
+        if file_type.is_symlink() {
+            let target = fs::read_link(path.to_path_buf())?;
+
+            hasher.update(b"l");
+            update_with_len(&mut hasher, relative.as_str().as_bytes());
+            update_with_len(&mut hasher, target.to_string_lossy().as_bytes());
+        } else if file_type.is_file() {
+            let mut file = fs::File::open(path.to_path_buf())?;
+            let metadata = file.metadata()?;
+
+            hasher.update(match is_executable(&metadata) {
+                true => b"x",
+                false => b"f",
+            });
+
+            update_with_len(&mut hasher, relative.as_str().as_bytes());
+            hasher.update(&metadata.len().to_le_bytes());
+
+            let mut buf = vec![0; compare::CHUNK_SIZE];
+            let mut count = 0;
+
+            loop {
+                let len = compare::read_full(&mut file, &mut buf)?;
+
+                if len == 0 {
+                    break;
+                }
+
+                hasher.update(&buf[..len]);
+                count += len as u64;
+            }
+
+            // The length was hashed upfront, so it must match what we read
+            if count != metadata.len() {
+                return Err(io::Error::other(format!("{} changed while being hashed", path)));
+            }
+        }
+    }
+
+    Ok(hasher.finalize())
+}
+
+#[cfg(feature = "sha2")]
+impl ContentHasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        sha2::Digest::finalize(self).to_vec()
+    }
+}
+
+#[cfg(feature = "sha2")]
+impl ContentHasher for sha2::Sha512 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        sha2::Digest::finalize(self).to_vec()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::TempDir;
+
+    // Keeps all the input around, so that tests can compare it directly
+    #[derive(Default)]
+    struct RecordingHasher(Vec<u8>);
+
+    impl ContentHasher for RecordingHasher {
+        fn update(&mut self, data: &[u8]) {
+            self.0.extend_from_slice(data);
+        }
+
+        fn finalize(self) -> Vec<u8> {
+            self.0
+        }
+    }
+
+    fn make_tree(dir: &Path) {
+        dir.with_join_str("src/lib.rs").fs_create_parent().unwrap();
+        dir.with_join_str("src/lib.rs").fs_write("pub fn lib() {}").unwrap();
+        dir.with_join_str("bin/run").fs_create_parent().unwrap();
+        dir.with_join_str("bin/run").fs_write("#!/bin/sh").unwrap();
+        dir.with_join_str("node_modules/dep/index.js").fs_create_parent().unwrap();
+        dir.with_join_str("node_modules/dep/index.js").fs_write("").unwrap();
+        dir.with_join_str("link").fs_symlink(&Path::from("src/lib.rs")).unwrap();
+    }
+
+    fn hash(dir: &Path, options: &HashTreeOptions) -> Vec<u8> {
+        dir.fs_hash_tree(RecordingHasher::default(), options).unwrap()
+    }
+
+    #[test]
+    fn test_hash_tree_stable() {
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+
+        make_tree(&first);
+        make_tree(&second);
+
+        assert_eq!(hash(&first, &HashTreeOptions::default()), hash(&second, &HashTreeOptions::default()));
+
+        second.with_join_str("bin/run").fs_set_permissions(fs::Permissions::from_mode(0o755)).unwrap();
+        assert_ne!(hash(&first, &HashTreeOptions::default()), hash(&second, &HashTreeOptions::default()));
+    }
+
+    #[test]
+    fn test_hash_tree_content() {
+        let dir = TempDir::new().unwrap();
+        make_tree(&dir);
+
+        let before = hash(&dir, &HashTreeOptions::default());
+
+        dir.with_join_str("link").fs_rm_file().unwrap();
+        dir.with_join_str("link").fs_symlink(&Path::from("bin/run")).unwrap();
+
+        assert_ne!(hash(&dir, &HashTreeOptions::default()), before);
+
+        // Moving bytes between the path and the content must change the hash
+        let a = TempDir::new().unwrap();
+        a.with_join_str("ab").fs_write("c").unwrap();
+
+        let b = TempDir::new().unwrap();
+        b.with_join_str("a").fs_write("bc").unwrap();
+
+        assert_ne!(hash(&a, &HashTreeOptions::default()), hash(&b, &HashTreeOptions::default()));
+    }
+
+    #[test]
+    fn test_hash_tree_ignore() {
+        let dir = TempDir::new().unwrap();
+        make_tree(&dir);
+
+        let options = HashTreeOptions {
+            ignored_names: vec!["node_modules".to_string()],
+            filter: Some(Arc::new(|path: &Path| path.as_str() != "bin/run")),
+        };
+
+        let before = hash(&dir, &options);
+
+        dir.with_join_str("node_modules/dep/index.js").fs_write("changed").unwrap();
+        dir.with_join_str("bin/run").fs_write("changed").unwrap();
+
+        assert_eq!(hash(&dir, &options), before);
+
+        dir.with_join_str("src/lib.rs").fs_write("changed").unwrap();
+        assert_ne!(hash(&dir, &options), before);
+    }
+
+    #[test]
+    fn test_hash_tree_ignore_root() {
+        let dir = TempDir::new().unwrap();
+        make_tree(&dir);
+
+        // The options only apply to the entries below the root
+        let root = dir.with_join_str("node_modules");
+
+        let ignored = HashTreeOptions {
+            ignored_names: vec!["node_modules".to_string()],
+            filter: Some(Arc::new(|path: &Path| !path.as_str().is_empty())),
+        };
+
+        let hashed = hash(&root, &ignored);
+        assert!(!hashed.is_empty());
+        assert_eq!(hashed, hash(&root, &HashTreeOptions::default()));
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.with_join_str("file");
+        file.fs_write("hello").unwrap();
+
+        assert_eq!(file.fs_hash(RecordingHasher::default()).unwrap(), b"hello");
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn test_hash_file_sha256() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.with_join_str("file");
+        file.fs_write("abc").unwrap();
+
+        assert_eq!(crate::format_hex(&file.fs_hash(sha2::Sha256::default()).unwrap()), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
@@ -1,19 +1,292 @@
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::ReadDir;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::str::FromStr;
 use std::{fs, io};
 
-use radix_trie::TrieCommon;
+mod compare;
+mod copy;
+mod diff;
+mod hash;
+mod lock;
+mod temp;
+mod vfs;
 
+pub mod changeset;
+pub mod multi_trie;
 pub mod path;
+pub mod persistent_trie;
+pub mod sync_dir;
+pub mod walk;
+
+#[cfg(feature = "tokio")]
+mod async_fs;
+
+#[cfg(feature = "tokio")]
+mod walk_stream;
+
+#[cfg(feature = "bincode")]
+pub mod fingerprint;
+
+#[cfg(target_os = "linux")]
+pub mod watch;
+
+#[cfg(feature = "mmap")]
+pub mod mapped_trie;
+
+pub use changeset::{Changeset, ChangesetMode};
+pub use compare::ContentHasher;
+pub use copy::{CopyOptions, CopyStrategy, OverwritePolicy};
+pub use hash::HashTreeOptions;
+pub use lock::{FileLock, LockKind, LockOptions};
+pub use multi_trie::{LayeredTrie, MultiTrie};
+pub use persistent_trie::PersistentTrie;
+pub use sync_dir::SyncEntry;
+pub use temp::{NamedTempFile, PersistError, TempDir};
+pub use vfs::{Fs, FsFileType, FsMetadata, FsPath, MemoryFs, RealFs};
+pub use walk::{ParallelWalk, Walk, WalkError};
+
+#[cfg(feature = "tokio")]
+pub use walk_stream::WalkStream;
+
+#[cfg(feature = "bincode")]
+pub use fingerprint::{Fingerprint, FingerprintStore};
+
+#[cfg(target_os = "linux")]
+pub use watch::{WatchEvent, Watcher};
+
+#[cfg(feature = "mmap")]
+pub use mapped_trie::MappedTrie;
+
+/// Summary of the first difference between the expected content of a file and
+/// its actual content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDiff {
+    /// Unknown when the expected content comes from a reader, which isn't
+    /// read past the first difference.
+    pub expected_size: Option<u64>,
+
+    pub actual_size: u64,
+
+    /// Offset of the first differing byte.
+    pub offset: u64,
+
+    /// Line (1-based) containing the first differing byte.
+    pub line: usize,
+
+    /// Unified diff going from the actual content to the expected one, only
+    /// computed on request and when both contents are valid UTF-8.
+    pub unified_diff: Option<String>,
+}
+
+impl ContentDiff {
+    fn unified(expected: &[u8], actual: &[u8], path: &Path) -> Option<String> {
+        match (std::str::from_utf8(expected), std::str::from_utf8(actual)) {
+            (Ok(expected), Ok(actual)) => Some(diff::unified_diff(actual, expected, &format!("{} (actual)", path), &format!("{} (expected)", path))),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum ImmutableErr {
-    Immutable,
+    Missing {
+        path: Path,
+    },
+
+    ContentMismatch {
+        path: Path,
+        diff: ContentDiff,
+    },
+
+    DigestMismatch {
+        path: Path,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+
+    PermissionsMismatch {
+        path: Path,
+        expected: fs::Permissions,
+        actual: fs::Permissions,
+    },
+
     Io(std::io::Error),
 }
 
+impl ImmutableErr {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ImmutableErr::Missing {path} => Some(path),
+            ImmutableErr::ContentMismatch {path, ..} => Some(path),
+            ImmutableErr::DigestMismatch {path, ..} => Some(path),
+            ImmutableErr::PermissionsMismatch {path, ..} => Some(path),
+            ImmutableErr::Io(_) => None,
+        }
+    }
+}
+
+// Permissions read from the filesystem also contain the file type bits, which
+// we don't want to take into account when comparing them.
+pub(crate) fn permissions_eq(a: &fs::Permissions, b: &fs::Permissions) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        a.mode() & 0o7777 == b.mode() & 0o7777
+    }
+
+    #[cfg(not(unix))]
+    {
+        a == b
+    }
+}
+
+fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn format_permissions(permissions: &fs::Permissions) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        format!("{:o}", permissions.mode() & 0o7777)
+    }
+
+    #[cfg(not(unix))]
+    {
+        if permissions.readonly() {
+            "readonly".to_string()
+        } else {
+            "writable".to_string()
+        }
+    }
+}
+
+impl Display for ImmutableErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImmutableErr::Missing {path} => {
+                write!(f, "{}: file is missing", path)
+            },
+
+            ImmutableErr::ContentMismatch {path, diff} => {
+                write!(f, "{}: content differs (", path)?;
+
+                if let Some(expected_size) = diff.expected_size {
+                    write!(f, "expected {} bytes, ", expected_size)?;
+                }
+
+                write!(f, "found {} bytes; first difference at byte {}, line {})", diff.actual_size, diff.offset, diff.line)?;
+
+                if let Some(unified_diff) = &diff.unified_diff {
+                    write!(f, "\n{}", unified_diff)?;
+                }
+
+                Ok(())
+            },
+
+            ImmutableErr::DigestMismatch {path, expected, actual} => {
+                write!(f, "{}: content digest differs (expected {}, found {})", path, format_hex(expected), format_hex(actual))
+            },
+
+            ImmutableErr::PermissionsMismatch {path, expected, actual} => {
+                write!(f, "{}: permissions differ (expected {}, found {})", path, format_permissions(expected), format_permissions(actual))
+            },
+
+            ImmutableErr::Io(err) => {
+                write!(f, "{}", err)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ImmutableErr {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImmutableErr::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ImmutableErr {
+    fn from(err: std::io::Error) -> Self {
+        ImmutableErr::Io(err)
+    }
+}
+
+/// What `fs_change` had to do to bring the file to the expected state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOutcome {
+    Created {
+        bytes_written: u64,
+    },
+
+    ContentUpdated {
+        previous_size: u64,
+        bytes_written: u64,
+    },
+
+    PermissionsUpdated,
+    Unchanged,
+}
+
+impl ChangeOutcome {
+    pub fn is_changed(&self) -> bool {
+        *self != ChangeOutcome::Unchanged
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        match self {
+            ChangeOutcome::Created {bytes_written} => *bytes_written,
+            ChangeOutcome::ContentUpdated {bytes_written, ..} => *bytes_written,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AtomicWriteOptions {
+    /// Fsync the file before renaming it, and its parent directory after.
+    pub sync: bool,
+
+    /// Permissions to apply to the new file. If unset, the permissions of the
+    /// file being replaced (if any) are preserved.
+    pub permissions: Option<fs::Permissions>,
+}
+
+impl Default for AtomicWriteOptions {
+    fn default() -> Self {
+        AtomicWriteOptions {
+            sync: true,
+            permissions: None,
+        }
+    }
+}
+
+const TEMP_ATTEMPTS: usize = 100;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn would_block_as_none<T>(err: io::Error) -> io::Result<Option<T>> {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => Ok(None),
+        _ => Err(err),
+    }
+}
+
+fn temp_nonce() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+
+    let counter = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    format!("{:x}-{:x}-{:x}", std::process::id(), nanos, counter)
+}
+
 pub trait OkMissing<T, E> {
     fn ok_missing(self) -> Result<Option<T>, E>;
 }
@@ -123,27 +396,26 @@ pub struct Path {
 }
 
 impl Path {
+    /// Creates a new directory in the system temporary directory, replacing
+    /// `<>` in the pattern by a unique nonce. Unlike `TempDir`, the directory
+    /// isn't removed automatically.
     pub fn temp_dir_pattern(str: &str) -> std::io::Result<Path> {
-        let index = str.find("<>")
-            .unwrap();
-
-        let before = &str[..index];
-        let after = &str[index + 2..];
-
-        let nonce = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-
-        let mut dir = std::env::temp_dir().to_arca();
-        let name = format!("{}{:032x}{}", before, nonce, after);
+        let parent = std::env::temp_dir().to_arca();
+        parent.fs_create_dir_all()?;
 
-        dir.join_str(name);
-        dir.fs_create_dir_all()?;
+        let (dir, _) = temp::create_unique(&parent, str, |path| {
+            fs::create_dir(path.to_path_buf())
+        })?;
 
         Ok(dir)
     }
 
+    /// Creates a new file in the system temporary directory, only readable by
+    /// the current user. It's removed on drop unless persisted.
+    pub fn temp_file() -> std::io::Result<NamedTempFile> {
+        NamedTempFile::new()
+    }
+
     pub fn temp_dir() -> std::io::Result<Path> {
         Self::temp_dir_pattern("temp-<>")
     }
@@ -242,6 +514,12 @@ impl Path {
         self.path.as_str()
     }
 
+    /// Binds the path to a filesystem, so that its `fs_*` methods go through
+    /// the `Fs` implementation instead of `std::fs`.
+    pub fn with_fs<'a, F: Fs + ?Sized>(&self, fs: &'a F) -> FsPath<'a, F> {
+        FsPath::new(fs, self)
+    }
+
     pub fn to_path_buf(&self) -> std::path::PathBuf {
         std::path::PathBuf::from(&self.path)
     }
@@ -305,6 +583,51 @@ impl Path {
         self.fs_metadata().map(|m| m.is_dir()).unwrap_or(false)
     }
 
+    pub fn fs_symlink_metadata(&self) -> io::Result<fs::Metadata> {
+        fs::symlink_metadata(&self.path)
+    }
+
+    /// Unlike `fs_exists` and `fs_is_dir`, doesn't follow symlinks.
+    pub fn fs_is_symlink(&self) -> bool {
+        self.fs_symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false)
+    }
+
+    /// Creates a symlink at this path pointing to `target`. Relative targets
+    /// are resolved from the directory containing the symlink.
+    pub fn fs_symlink(&self, target: &Path) -> io::Result<&Self> {
+        changeset::create_symlink(target, self)?;
+        Ok(self)
+    }
+
+    /// Same as `fs_symlink`, but stores the target relative to the directory
+    /// containing the symlink (see `relative_link_target`). Fails with
+    /// `InvalidInput` if either path isn't absolute.
+    pub fn fs_symlink_relative(&self, target: &Path) -> io::Result<&Self> {
+        self.fs_symlink(&self.checked_relative_link_target(target)?)
+    }
+
+    pub fn fs_read_link(&self) -> io::Result<Path> {
+        Ok(fs::read_link(&self.path)?.to_arca())
+    }
+
+    /// Creates a hardlink at this path pointing to the same file as `target`.
+    pub fn fs_hardlink(&self, target: &Path) -> io::Result<&Self> {
+        fs::hard_link(target.to_path_buf(), self.to_path_buf())?;
+        Ok(self)
+    }
+
+    #[cfg(unix)]
+    pub fn fs_link_count(&self) -> io::Result<u64> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(self.fs_symlink_metadata()?.nlink())
+    }
+
+    #[cfg(not(unix))]
+    pub fn fs_link_count(&self) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Link counts aren't supported on this platform"))
+    }
+
     pub fn if_exists(&self) -> Option<Path> {
         if self.fs_exists() {
             Some(self.clone())
@@ -367,15 +690,54 @@ impl Path {
         Ok(data)
     }
 
-    #[cfg(feature = "tokio")]
-    pub async fn fs_read_text_async(&self) -> io::Result<String> {
-        tokio::fs::read_to_string(self.to_path_buf()).await
-    }
-
     pub fn fs_read_dir(&self) -> io::Result<ReadDir> {
         fs::read_dir(&self.to_path_buf())
     }
 
+    /// Returns the entries of the directory joined to it, along with their
+    /// (non-followed) file type.
+    pub fn fs_read_dir_entries(&self) -> io::Result<Vec<(Path, fs::FileType)>> {
+        self.fs_read_dir()?
+            .map(|entry| {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+
+                Ok((self.with_entry_name(&entry.file_name().to_string_lossy()), file_type))
+            })
+            .collect()
+    }
+
+    pub fn fs_read_dir_entries_sorted(&self) -> io::Result<Vec<(Path, fs::FileType)>> {
+        let mut entries = self.fs_read_dir_entries()?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    pub fn fs_read_dir_names(&self) -> io::Result<Vec<String>> {
+        self.fs_read_dir()?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    /// Recursively iterates over the directory. See `Walk` for the available
+    /// options.
+    pub fn fs_walk(&self) -> Walk {
+        Walk::new(self)
+    }
+
+    /// Recursively iterates over the directory using multiple threads. See
+    /// `ParallelWalk` for the available options.
+    pub fn fs_walk_parallel(&self) -> ParallelWalk {
+        ParallelWalk::new(self)
+    }
+
+    /// Recursively iterates over the directory as an asynchronous stream. See
+    /// `WalkStream` for the available options.
+    #[cfg(feature = "tokio")]
+    pub fn fs_walk_stream(&self) -> WalkStream {
+        WalkStream::new(self)
+    }
+
     pub fn fs_write<T: AsRef<[u8]>>(&self, data: T) -> io::Result<&Self> {
         fs::write(self.to_path_buf(), data)?;
         Ok(self)
@@ -387,145 +749,547 @@ impl Path {
     }
 
     pub fn fs_expect<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> Result<&Self, ImmutableErr> {
-        let path_buf = self.to_path_buf();
+        self.fs_expect_impl(data, permissions, false)
+    }
 
-        let update_content = std::fs::read(&path_buf)
-            .ok_missing()
-            .map(|current| current.map(|current| current.ne(data.as_ref())).unwrap_or(true))
-            .map_err(ImmutableErr::Io)?;
+    /// Same as `fs_expect`, but content mismatches on text files also include
+    /// a unified diff between the actual and expected contents.
+    pub fn fs_expect_with_diff<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> Result<&Self, ImmutableErr> {
+        self.fs_expect_impl(data, permissions, true)
+    }
 
-        if update_content {
-            return Err(ImmutableErr::Immutable);
-        }
+    fn fs_expect_impl<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions, with_unified_diff: bool) -> Result<&Self, ImmutableErr> {
+        let data = data.as_ref();
+        let mut file = self.fs_expect_open()?;
+        let metadata = file.metadata()?;
 
-        let update_permissions = update_content ||
-            std::fs::metadata(&path_buf).map_err(ImmutableErr::Io)?.permissions() != permissions;
+        let mismatch = match with_unified_diff {
+            // The whole file is needed for the unified diff anyway
+            true => {
+                let actual = self.fs_read()?;
 
-        if update_permissions {
-            return Err(ImmutableErr::Immutable);
+                compare::compare_streams(&mut &data[..], &mut &actual[..])?
+                    .map(|stream_diff| (stream_diff, ContentDiff::unified(data, &actual, self)))
+            },
+
+            // Streamed even when the sizes differ, so that the location of
+            // the first difference is always reported
+            false => {
+                compare::compare_streams(&mut &data[..], &mut file)?
+                    .map(|stream_diff| (stream_diff, None))
+            },
+        };
+
+        if let Some((stream_diff, unified_diff)) = mismatch {
+            return Err(ImmutableErr::ContentMismatch {
+                path: self.clone(),
+                diff: ContentDiff {
+                    expected_size: Some(data.len() as u64),
+                    actual_size: metadata.len(),
+                    offset: stream_diff.offset,
+                    line: stream_diff.line,
+                    unified_diff,
+                },
+            });
         }
 
+        self.fs_expect_permissions(&metadata, permissions)?;
         Ok(self)
     }
 
-    pub fn fs_change<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> io::Result<&Self> {
-        let path_buf = self.to_path_buf();
+    /// Same as `fs_expect`, but the expected content is streamed from the
+    /// provided reader rather than being held in memory. The reader isn't
+    /// consumed past the chunk holding the first difference, so on mismatch
+    /// the expected size is only reported if the reader ended there.
+    pub fn fs_expect_from_reader<R: Read>(&self, reader: R, permissions: fs::Permissions) -> Result<&Self, ImmutableErr> {
+        let mut file = self.fs_expect_open()?;
+        let metadata = file.metadata()?;
 
-        let update_content = std::fs::read(&path_buf)
-            .ok_missing()
-            .map(|current| current.map(|current| current.ne(data.as_ref())).unwrap_or(true))?;
+        let mut expected = compare::CountingReader {
+            inner: reader,
+            count: 0,
+            eof: false,
+        };
 
-        if update_content {
-            std::fs::write(&path_buf, data)?;
+        if let Some(stream_diff) = compare::compare_streams(&mut expected, &mut file)? {
+            // The rest of the reader isn't drained, so its size is only known
+            // if the difference was found at its end
+            return Err(ImmutableErr::ContentMismatch {
+                path: self.clone(),
+                diff: ContentDiff {
+                    expected_size: expected.eof.then_some(expected.count),
+                    actual_size: metadata.len(),
+                    offset: stream_diff.offset,
+                    line: stream_diff.line,
+                    unified_diff: None,
+                },
+            });
         }
 
-        let update_permissions = update_content ||
-            std::fs::metadata(&path_buf)?.permissions() != permissions;
+        self.fs_expect_permissions(&metadata, permissions)?;
+        Ok(self)
+    }
 
-        if update_permissions {
-            std::fs::set_permissions(&path_buf, permissions)?;
+    /// Same as `fs_expect`, but the file content is checked against a
+    /// precomputed digest, hashing the file as it's being read.
+    pub fn fs_expect_digest<H: ContentHasher>(&self, digest: &[u8], hasher: H, permissions: fs::Permissions) -> Result<&Self, ImmutableErr> {
+        let mut file = self.fs_expect_open()?;
+        let metadata = file.metadata()?;
+
+        let actual = compare::hash_stream(&mut file, hasher)?;
+
+        if actual != digest {
+            return Err(ImmutableErr::DigestMismatch {
+                path: self.clone(),
+                expected: digest.to_vec(),
+                actual,
+            });
         }
 
+        self.fs_expect_permissions(&metadata, permissions)?;
         Ok(self)
     }
 
-    pub fn fs_rename(&self, new_path: &Path) -> io::Result<&Self> {
-        fs::rename(self.to_path_buf(), new_path.to_path_buf())?;
-        Ok(self)
+    /// Hashes the content of the file without loading it in memory.
+    pub fn fs_hash<H: ContentHasher>(&self, hasher: H) -> io::Result<Vec<u8>> {
+        let mut file = fs::File::open(self.to_path_buf())?;
+        compare::hash_stream(&mut file, hasher)
     }
 
-    pub fn fs_rm_file(&self) -> io::Result<&Self> {
-        fs::remove_file(self.to_path_buf())?;
-        Ok(self)
+    /// Computes a reproducible hash of the directory, covering the relative
+    /// paths, contents, executable bits, and symlink targets of its files.
+    pub fn fs_hash_tree<H: ContentHasher>(&self, hasher: H, options: &HashTreeOptions) -> io::Result<Vec<u8>> {
+        hash::hash_tree(self, hasher, options)
     }
 
-    pub fn fs_rm(&self) -> io::Result<&Self> {
-        match self.fs_is_dir() {
-            true => fs::remove_dir_all(self.to_path_buf()),
-            false => fs::remove_file(self.to_path_buf()),
-        }?;
+    fn fs_expect_open(&self) -> Result<fs::File, ImmutableErr> {
+        fs::File::open(self.to_path_buf())
+            .ok_missing()?
+            .ok_or_else(|| ImmutableErr::Missing {path: self.clone()})
+    }
 
-        Ok(self)
+    fn fs_expect_permissions(&self, metadata: &fs::Metadata, permissions: fs::Permissions) -> Result<(), ImmutableErr> {
+        let current_permissions = metadata.permissions();
+
+        if !permissions_eq(&current_permissions, &permissions) {
+            return Err(ImmutableErr::PermissionsMismatch {
+                path: self.clone(),
+                expected: permissions,
+                actual: current_permissions,
+            });
+        }
+
+        Ok(())
     }
 
-    pub fn without_ext(&self) -> Path {
-        self.with_ext("")
+    pub fn fs_change<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> io::Result<ChangeOutcome> {
+        self.fs_change_impl(data, permissions, false)
     }
 
-    pub fn with_ext(&self, ext: &str) -> Path {
-        let mut copy = self.clone();
-        copy.set_ext(ext);
-        copy
+    /// Same as `fs_change`, but the content is written through
+    /// `fs_write_atomic_with` so that readers never see a partial file.
+    pub fn fs_change_atomic<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions) -> io::Result<ChangeOutcome> {
+        self.fs_change_impl(data, permissions, true)
     }
 
-    pub fn set_ext(&mut self, ext: &str) -> &mut Self {
-        let has_trailing_slash = self.path.ends_with('/');
+    fn fs_change_impl<T: AsRef<[u8]>>(&self, data: T, permissions: fs::Permissions, atomic: bool) -> io::Result<ChangeOutcome> {
+        let path_buf = self.to_path_buf();
+        let data = data.as_ref();
 
-        let initial_slice = if has_trailing_slash {
-            &self.path[..self.path.len() - 1]
-        } else {
-            &self.path
+        let outcome = match fs::File::open(&path_buf).ok_missing()? {
+            None => ChangeOutcome::Created {
+                bytes_written: data.len() as u64,
+            },
+
+            Some(mut file) => {
+                let previous_size = file.metadata()?.len();
+
+                // Only compare the content if the size matches
+                let update_content = previous_size != data.len() as u64
+                    || compare::compare_streams(&mut &data[..], &mut file)?.is_some();
+
+                match update_content {
+                    true => ChangeOutcome::ContentUpdated {
+                        previous_size,
+                        bytes_written: data.len() as u64,
+                    },
+
+                    false => ChangeOutcome::Unchanged,
+                }
+            },
         };
 
-        let first_basename_char = initial_slice
-            .rfind('/')
-            .map(|i| i + 1)
-            .unwrap_or(0);
+        let update_content = outcome != ChangeOutcome::Unchanged;
 
-        let mut ext_char = self.path[first_basename_char..]
-            .rfind('.')
-            .map(|i| i + first_basename_char)
-            .unwrap_or(initial_slice.len());
+        if update_content && atomic {
+            self.fs_write_atomic_with(data, &AtomicWriteOptions {
+                permissions: Some(permissions),
+                ..AtomicWriteOptions::default()
+            })?;
 
-        if ext_char == first_basename_char {
-            ext_char = self.path.len();
+            return Ok(outcome);
         }
 
-        if ext_char > 2 && &self.path[ext_char - 2..] == ".d.ts" {
-            ext_char -= 2;
+        if update_content {
+            std::fs::write(&path_buf, data)?;
         }
 
-        let mut copy = self.path[..ext_char].to_string();
-        copy.push_str(ext);
+        let update_permissions = update_content ||
+            !permissions_eq(&std::fs::metadata(&path_buf)?.permissions(), &permissions);
 
-        if has_trailing_slash {
-            copy.push('/');
+        if update_permissions {
+            std::fs::set_permissions(&path_buf, permissions)?;
         }
 
-        self.path = copy;
-        self
+        match outcome {
+            ChangeOutcome::Unchanged if update_permissions => Ok(ChangeOutcome::PermissionsUpdated),
+            outcome => Ok(outcome),
+        }
     }
 
-    pub fn with_join(&self, other: &Path) -> Path {
-        let mut copy = self.clone();
-        copy.join(other);
-        copy
-    }
+    /// Same as `fs_change`, but the content is streamed from the provided
+    /// reader. It's written into a temporary sibling file while being compared
+    /// with the current content, then moved into place if they differ.
+    pub fn fs_change_from_reader<R: Read>(&self, mut reader: R, permissions: fs::Permissions) -> io::Result<ChangeOutcome> {
+        let path_buf = self.to_path_buf();
 
-    pub fn with_join_str<T>(&self, other: T) -> Path
-    where
-        T: AsRef<str>,
-    {
-        let mut copy = self.clone();
-        copy.join_str(other);
-        copy
-    }
+        let mut current = fs::File::open(&path_buf)
+            .ok_missing()?;
 
-    pub fn join(&mut self, other: &Path) -> &mut Self {
-        if !other.path.is_empty() {
-            if self.path.is_empty() || other.is_absolute() {
-                self.path = other.path.clone();
-            } else {
-                if !self.path.ends_with('/') {
-                    self.path.push('/');
-                }
-                self.path.push_str(&other.path);
-                self.normalize();
-            }
-        }
+        let previous_size = match &current {
+            Some(file) => Some(file.metadata()?.len()),
+            None => None,
+        };
 
-        self
-    }
+        let (temp_path, mut temp_file) = self.fs_create_sibling_temp()?;
+        let temp_path_buf = temp_path.to_path_buf();
+
+        let write_result = (|| {
+            let mut buf = vec![0; compare::CHUNK_SIZE];
+            let mut current_buf = vec![0; compare::CHUNK_SIZE];
+
+            let mut identical = previous_size.is_some();
+            let mut bytes_written = 0;
+
+            loop {
+                let len = compare::read_full(&mut reader, &mut buf)?;
+
+                if len == 0 {
+                    break;
+                }
+
+                if let (true, Some(current)) = (identical, current.as_mut()) {
+                    let current_len = compare::read_full(current, &mut current_buf[..len])?;
+                    identical = buf[..len] == current_buf[..current_len];
+                }
+
+                temp_file.write_all(&buf[..len])?;
+                bytes_written += len as u64;
+            }
+
+            if identical && previous_size == Some(bytes_written) {
+                return Ok(ChangeOutcome::Unchanged);
+            }
+
+            temp_file.set_permissions(permissions.clone())?;
+            temp_file.sync_all()?;
+
+            fs::rename(&temp_path_buf, &path_buf)?;
+
+            Ok(match previous_size {
+                Some(previous_size) => ChangeOutcome::ContentUpdated {previous_size, bytes_written},
+                None => ChangeOutcome::Created {bytes_written},
+            })
+        })();
+
+        drop(temp_file);
+
+        match write_result {
+            Ok(ChangeOutcome::Unchanged) => {
+                fs::remove_file(&temp_path_buf)?;
+
+                if self.fs_change_permissions(permissions)? {
+                    Ok(ChangeOutcome::PermissionsUpdated)
+                } else {
+                    Ok(ChangeOutcome::Unchanged)
+                }
+            },
+
+            Ok(outcome) => {
+                self.fs_sync_parent()?;
+                Ok(outcome)
+            },
+
+            Err(err) => {
+                let _ = fs::remove_file(&temp_path_buf);
+                Err(err)
+            },
+        }
+    }
+
+    /// Same as `fs_change_from_reader`, except that if the current content
+    /// matches the given digest the reader won't be consumed at all.
+    pub fn fs_change_with_digest<R: Read, H: ContentHasher>(&self, digest: &[u8], hasher: H, reader: R, permissions: fs::Permissions) -> io::Result<ChangeOutcome> {
+        if let Some(mut file) = fs::File::open(self.to_path_buf()).ok_missing()? {
+            if compare::hash_stream(&mut file, hasher)? == digest {
+                return match self.fs_change_permissions(permissions)? {
+                    true => Ok(ChangeOutcome::PermissionsUpdated),
+                    false => Ok(ChangeOutcome::Unchanged),
+                };
+            }
+        }
+
+        self.fs_change_from_reader(reader, permissions)
+    }
+
+    fn fs_change_permissions(&self, permissions: fs::Permissions) -> io::Result<bool> {
+        let path_buf = self.to_path_buf();
+
+        if permissions_eq(&fs::metadata(&path_buf)?.permissions(), &permissions) {
+            return Ok(false);
+        }
+
+        fs::set_permissions(&path_buf, permissions)?;
+        Ok(true)
+    }
+
+    /// Makes the content of the directory match exactly the given entries,
+    /// whose keys are paths relative to the directory. Files are compared and
+    /// written with the same semantic as `fs_change`, and entries not listed
+    /// are removed. Use `ChangesetMode::Check` to only verify the directory.
+    pub fn fs_sync_dir(&self, entries: &std::collections::BTreeMap<Path, SyncEntry>, mode: ChangesetMode) -> Result<changeset::ChangesetReport, changeset::ChangesetErr> {
+        sync_dir::sync_dir(self, entries, mode)
+    }
+
+    /// Writes the file by first writing the data into a temporary sibling
+    /// file, then renaming it into place. Readers will either see the old
+    /// content or the new one, never a partially written file.
+    pub fn fs_write_atomic<T: AsRef<[u8]>>(&self, data: T) -> io::Result<&Self> {
+        self.fs_write_atomic_with(data, &AtomicWriteOptions::default())
+    }
+
+    pub fn fs_write_atomic_with<T: AsRef<[u8]>>(&self, data: T, options: &AtomicWriteOptions) -> io::Result<&Self> {
+        let path_buf = self.to_path_buf();
+
+        let permissions = match &options.permissions {
+            Some(permissions) => Some(permissions.clone()),
+            None => fs::metadata(&path_buf).ok_missing()?.map(|m| m.permissions()),
+        };
+
+        let (temp_path, mut file) = self.fs_create_sibling_temp()?;
+        let temp_path_buf = temp_path.to_path_buf();
+
+        let write_result = (|| {
+            file.write_all(data.as_ref())?;
+
+            if let Some(permissions) = permissions {
+                file.set_permissions(permissions)?;
+            }
+
+            if options.sync {
+                file.sync_all()?;
+            }
+
+            drop(file);
+            fs::rename(&temp_path_buf, &path_buf)
+        })();
+
+        if let Err(err) = write_result {
+            let _ = fs::remove_file(&temp_path_buf);
+            return Err(err);
+        }
+
+        if options.sync {
+            self.fs_sync_parent()?;
+        }
+
+        Ok(self)
+    }
+
+    fn fs_create_sibling_temp(&self) -> io::Result<(Path, fs::File)> {
+        let basename = self.basename()
+            .unwrap_or("file");
+
+        for _ in 0..TEMP_ATTEMPTS {
+            let name = format!(".{}.{}.tmp", basename, temp_nonce());
+
+            let temp_path = match self.dirname() {
+                Some(dirname) => dirname.with_join_str(name),
+                None => Path::from(name),
+            };
+
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(temp_path.to_path_buf());
+
+            match file {
+                Ok(file) => return Ok((temp_path, file)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::AlreadyExists, "Failed to find an available temporary file name"))
+    }
+
+    fn fs_sync_parent(&self) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            let parent = self.dirname()
+                .unwrap_or_else(|| Path::from("."));
+
+            fs::File::open(parent.to_path_buf())?.sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the file or directory tree to `destination`, using the strategy
+    /// and overwrite policy from the options. Symlinks are copied as-is.
+    pub fn fs_copy(&self, destination: &Path, options: &CopyOptions) -> io::Result<&Self> {
+        copy::copy(self, destination, options)?;
+        Ok(self)
+    }
+
+    /// Blocks until an exclusive advisory lock is acquired on the file, which
+    /// is created if needed. The lock is released when the guard is dropped.
+    pub fn fs_lock_exclusive(&self) -> io::Result<FileLock> {
+        self.fs_lock_with(&LockOptions {kind: LockKind::Exclusive, ..LockOptions::default()})
+    }
+
+    pub fn fs_lock_shared(&self) -> io::Result<FileLock> {
+        self.fs_lock_with(&LockOptions {kind: LockKind::Shared, ..LockOptions::default()})
+    }
+
+    /// Returns `None` rather than waiting if the lock is already held.
+    pub fn fs_try_lock_exclusive(&self) -> io::Result<Option<FileLock>> {
+        self.fs_lock_with(&LockOptions {kind: LockKind::Exclusive, timeout: Some(std::time::Duration::ZERO), ..LockOptions::default()})
+            .map(Some)
+            .or_else(would_block_as_none)
+    }
+
+    pub fn fs_try_lock_shared(&self) -> io::Result<Option<FileLock>> {
+        self.fs_lock_with(&LockOptions {kind: LockKind::Shared, timeout: Some(std::time::Duration::ZERO), ..LockOptions::default()})
+            .map(Some)
+            .or_else(would_block_as_none)
+    }
+
+    pub fn fs_lock_with(&self, options: &LockOptions) -> io::Result<FileLock> {
+        lock::lock(self, options)
+    }
+
+    pub fn fs_rename(&self, new_path: &Path) -> io::Result<&Self> {
+        fs::rename(self.to_path_buf(), new_path.to_path_buf())?;
+        Ok(self)
+    }
+
+    pub fn fs_rm_file(&self) -> io::Result<&Self> {
+        fs::remove_file(self.to_path_buf())?;
+        Ok(self)
+    }
+
+    pub fn fs_rm(&self) -> io::Result<&Self> {
+        match self.fs_is_dir() {
+            true => fs::remove_dir_all(self.to_path_buf()),
+            false => fs::remove_file(self.to_path_buf()),
+        }?;
+
+        Ok(self)
+    }
+
+    pub fn without_ext(&self) -> Path {
+        self.with_ext("")
+    }
+
+    pub fn with_ext(&self, ext: &str) -> Path {
+        let mut copy = self.clone();
+        copy.set_ext(ext);
+        copy
+    }
+
+    pub fn set_ext(&mut self, ext: &str) -> &mut Self {
+        let has_trailing_slash = self.path.ends_with('/');
+
+        let initial_slice = if has_trailing_slash {
+            &self.path[..self.path.len() - 1]
+        } else {
+            &self.path
+        };
+
+        let first_basename_char = initial_slice
+            .rfind('/')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+
+        let mut ext_char = self.path[first_basename_char..]
+            .rfind('.')
+            .map(|i| i + first_basename_char)
+            .unwrap_or(initial_slice.len());
+
+        if ext_char == first_basename_char {
+            ext_char = self.path.len();
+        }
+
+        if ext_char > 2 && &self.path[ext_char - 2..] == ".d.ts" {
+            ext_char -= 2;
+        }
+
+        let mut copy = self.path[..ext_char].to_string();
+        copy.push_str(ext);
+
+        if has_trailing_slash {
+            copy.push('/');
+        }
+
+        self.path = copy;
+        self
+    }
+
+    pub fn with_join(&self, other: &Path) -> Path {
+        let mut copy = self.clone();
+        copy.join(other);
+        copy
+    }
+
+    pub fn with_join_str<T>(&self, other: T) -> Path
+    where
+        T: AsRef<str>,
+    {
+        let mut copy = self.clone();
+        copy.join_str(other);
+        copy
+    }
+
+    pub fn join(&mut self, other: &Path) -> &mut Self {
+        if !other.path.is_empty() {
+            if self.path.is_empty() || other.is_absolute() {
+                self.path = other.path.clone();
+            } else {
+                if !self.path.ends_with('/') {
+                    self.path.push('/');
+                }
+                self.path.push_str(&other.path);
+                self.normalize();
+            }
+        }
+
+        self
+    }
+
+    // Directory entry names can't contain separators nor be `.` or `..`, so
+    // there's no need to normalize the result.
+    pub(crate) fn with_entry_name(&self, name: &str) -> Path {
+        let mut path = String::with_capacity(self.path.len() + name.len() + 1);
+        path.push_str(&self.path);
+
+        if !path.is_empty() && !path.ends_with('/') {
+            path.push('/');
+        }
+
+        path.push_str(name);
+        Path {path}
+    }
 
     pub fn join_str<T>(&mut self, other: T) -> &mut Self
     where
@@ -575,6 +1339,25 @@ impl Path {
         }
     }
 
+    /// Returns the target a symlink located at this path should contain to
+    /// point to `target`, or `None` if either path isn't absolute.
+    pub fn relative_link_target(&self, target: &Path) -> Option<Path> {
+        if !self.is_absolute() || !target.is_absolute() {
+            return None;
+        }
+
+        let dirname = self.dirname()
+            .unwrap_or_else(|| Path::from("/"));
+
+        Some(target.relative_to(&dirname))
+    }
+
+    pub(crate) fn checked_relative_link_target(&self, target: &Path) -> io::Result<Path> {
+        self.relative_link_target(target).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Expected absolute paths, got {} and {}", self, target))
+        })
+    }
+
     fn normalize(&mut self) {
         self.path = resolve_path(&self.path);
     }
@@ -727,9 +1510,193 @@ fn resolve_path(input: &str) -> String {
     }
 }
 
+fn cmp_first_segment(a: &str, b: &str) -> std::cmp::Ordering {
+    for (ca, cb) in a.bytes().zip(b.bytes()) {
+        if ca != cb {
+            return ca.cmp(&cb);
+        }
+
+        if ca == b'/' {
+            break;
+        }
+    }
+
+    std::cmp::Ordering::Equal
+}
+
+fn common_segments_len(a: &str, b: &str) -> usize {
+    let mut len = 0;
+
+    for (i, (ca, cb)) in a.bytes().zip(b.bytes()).enumerate() {
+        if ca != cb {
+            break;
+        }
+
+        if ca == b'/' {
+            len = i + 1;
+        }
+    }
+
+    len
+}
+
+/// A node of the path radix tree. Each label is made of one or more whole
+/// segments, each of them followed by a slash (so "/usr/" is the "" segment
+/// followed by the "usr" segment). Children are sorted by their first segment,
+/// which no two siblings share.
+///
+/// Keys are only stored once: each node keeps the full path leading to it
+/// (which is the key of its entry, if any), and its label is the part of that
+/// path following the path of its parent.
+#[derive(Debug, Clone)]
+struct TrieNode<T> {
+    path: Path,
+    label_start: usize,
+    value: Option<T>,
+    children: Vec<TrieNode<T>>,
+}
+
+impl<T> Default for TrieNode<T> {
+    fn default() -> Self {
+        TrieNode::new("", 0, None)
+    }
+}
+
+impl<T> TrieNode<T> {
+    fn new(path: &str, label_start: usize, value: Option<T>) -> Self {
+        TrieNode {
+            path: Path {path: path.to_string()},
+            label_start,
+            value,
+            children: Vec::new(),
+        }
+    }
+
+    fn label(&self) -> &str {
+        &self.path.path[self.label_start..]
+    }
+
+    /// Part of the key that's left to match once this node is reached. The
+    /// key must start with the path of the node.
+    fn rest<'a>(&self, key: &'a str) -> &'a str {
+        &key[self.path.path.len()..]
+    }
+
+    fn child_index(&self, rest: &str) -> Result<usize, usize> {
+        self.children.binary_search_by(|child| cmp_first_segment(child.label(), rest))
+    }
+
+    /// Returns the child leading to the key, if the key starts with its path.
+    fn child_towards(&self, key: &str) -> Option<usize> {
+        let index = self.child_index(self.rest(key)).ok()?;
+
+        match key.starts_with(self.children[index].path.as_str()) {
+            true => Some(index),
+            false => None,
+        }
+    }
+
+    fn find(&self, key: &str) -> Option<&TrieNode<T>> {
+        let mut node = self;
+
+        while !node.rest(key).is_empty() {
+            node = &node.children[node.child_towards(key)?];
+        }
+
+        Some(node)
+    }
+
+    fn find_mut(&mut self, key: &str) -> Option<&mut TrieNode<T>> {
+        if self.rest(key).is_empty() {
+            return Some(self);
+        }
+
+        let index = self.child_towards(key)?;
+        self.children[index].find_mut(key)
+    }
+
+    fn find_ancestor(&self, key: &str) -> Option<(&Path, &T)> {
+        let mut node = self;
+        let mut best = None;
+
+        while !node.rest(key).is_empty() {
+            let Some(index) = node.child_towards(key) else {
+                break;
+            };
+
+            node = &node.children[index];
+
+            if let Some(value) = &node.value {
+                best = Some((&node.path, value));
+            }
+        }
+
+        best
+    }
+
+    fn insert(&mut self, key: &str, value: T) -> Option<T> {
+        let rest = self.rest(key);
+
+        if rest.is_empty() {
+            return self.value.replace(value);
+        }
+
+        let path_len = self.path.path.len();
+
+        match self.child_index(rest) {
+            Err(index) => {
+                self.children.insert(index, TrieNode::new(key, path_len, Some(value)));
+                None
+            },
+
+            Ok(index) => {
+                let child = &mut self.children[index];
+                let common_len = common_segments_len(child.label(), rest);
+
+                if common_len < child.label().len() {
+                    let mut tail = std::mem::replace(child, TrieNode::new(&key[..path_len + common_len], path_len, None));
+                    tail.label_start = path_len + common_len;
+                    child.children.push(tail);
+                }
+
+                child.insert(key, value)
+            },
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<T> {
+        if self.rest(key).is_empty() {
+            return self.value.take();
+        }
+
+        let index = self.child_towards(key)?;
+        let child = &mut self.children[index];
+        let removed = child.remove(key)?;
+
+        if child.value.is_none() {
+            match child.children.len() {
+                0 => {
+                    self.children.remove(index);
+                },
+
+                1 => {
+                    let mut grandchild = child.children.pop().unwrap();
+                    grandchild.label_start = child.label_start;
+                    *child = grandchild;
+                },
+
+                _ => {},
+            }
+        }
+
+        Some(removed)
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Trie<T> {
-    inner: radix_trie::Trie<String, (Path, T)>,
+    root: TrieNode<T>,
+    len: usize,
 }
 
 impl<T> Trie<T> {
@@ -744,43 +1711,158 @@ impl<T> Trie<T> {
     }
 
     pub fn get(&self, key: &Path) -> Option<&T> {
-        self.inner.get(&self.key(&key)).map(|t| &t.1)
+        self.root.find(&self.key(key))
+            .and_then(|n| n.value.as_ref())
     }
 
     pub fn get_mut(&mut self, key: &Path) -> Option<&mut T> {
-        self.inner.get_mut(&self.key(&key)).map(|t| &mut t.1)
+        let k = self.key(key);
+
+        self.root.find_mut(&k)
+            .and_then(|n| n.value.as_mut())
     }
 
     pub fn get_ancestor_record(&self, key: &Path) -> Option<(&String, &Path, &T)> {
-        self.inner.get_ancestor(&self.key(&key)).map(|e| {
-            let k = e.key().unwrap();
-            let v = e.value().unwrap();
-
-            (k, &v.0, &v.1)
-        })
+        self.root.find_ancestor(&self.key(key))
+            .map(|(path, value)| (&path.path, path, value))
     }
 
     pub fn get_ancestor_key(&self, key: &Path) -> Option<&String> {
-        self.inner.get_ancestor(&self.key(&key)).and_then(|e| e.key())
+        self.root.find_ancestor(&self.key(key)).map(|(path, _)| &path.path)
     }
 
     pub fn get_ancestor_path(&self, key: &Path) -> Option<&Path> {
-        self.inner.get_ancestor_value(&self.key(&key)).map(|t| &t.0)
+        self.root.find_ancestor(&self.key(key)).map(|(path, _)| path)
     }
 
     pub fn get_ancestor_value(&self, key: &Path) -> Option<&T> {
-        self.inner.get_ancestor_value(&self.key(&key)).map(|t| &t.1)
+        self.root.find_ancestor(&self.key(key)).map(|(_, value)| value)
     }
 
     pub fn insert(&mut self, key: Path, value: T) -> () {
-        let k = self.key(&key);
-        let p = Path::from(k.clone());
-
-        self.inner.insert(k, (p, value)).map(|t| t.1);
+        if self.root.insert(&self.key(&key), value).is_none() {
+            self.len += 1;
+        }
     }
 
     pub fn remove(&mut self, key: &Path) -> () {
-        self.inner.remove(&self.key(&key));
+        if self.root.remove(&self.key(key)).is_some() {
+            self.len -= 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over all the entries in the trie, sorted by key.
+    pub fn iter(&self) -> TrieIter<'_, T> {
+        TrieIter {
+            stack: vec![&self.root],
+        }
+    }
+
+    /// Compares two tries and yields the entries that must be added, removed,
+    /// or changed to turn `self` into `other`, sorted by key.
+    pub fn diff<'a>(&'a self, other: &'a Trie<T>) -> TrieDiff<'a, T> {
+        TrieDiff {
+            before: self.iter().peekable(),
+            after: other.iter().peekable(),
+        }
+    }
+
+    /// Returns a new trie containing the entries from both tries. When a key
+    /// exists in both, the resolver is called with the values from `self` and
+    /// `other` (in this order) to compute the merged value.
+    pub fn merge<F>(&self, other: &Trie<T>, mut resolver: F) -> Trie<T>
+    where
+        T: Clone,
+        F: FnMut(&Path, &T, &T) -> T,
+    {
+        let mut merged = self.clone();
+
+        for (path, value) in other.iter() {
+            let merged_value = match self.get(path) {
+                Some(current) => resolver(path, current, value),
+                None => value.clone(),
+            };
+
+            merged.insert(path.clone(), merged_value);
+        }
+
+        merged
+    }
+}
+
+pub struct TrieIter<'a, T> {
+    stack: Vec<&'a TrieNode<T>>,
+}
+
+impl<'a, T> Iterator for TrieIter<'a, T> {
+    type Item = (&'a Path, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            self.stack.extend(node.children.iter().rev());
+
+            if let Some(value) = &node.value {
+                return Some((&node.path, value));
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrieChange<'a, T> {
+    Added(&'a Path, &'a T),
+    Removed(&'a Path, &'a T),
+    Changed(&'a Path, &'a T, &'a T),
+}
+
+pub struct TrieDiff<'a, T> {
+    before: std::iter::Peekable<TrieIter<'a, T>>,
+    after: std::iter::Peekable<TrieIter<'a, T>>,
+}
+
+impl<'a, T: PartialEq> Iterator for TrieDiff<'a, T> {
+    type Item = TrieChange<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ordering = match (self.before.peek(), self.after.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (Some(before), Some(after)) => before.0.cmp(after.0),
+            };
+
+            match ordering {
+                std::cmp::Ordering::Less => {
+                    let (path, value) = self.before.next().unwrap();
+                    return Some(TrieChange::Removed(path, value));
+                },
+
+                std::cmp::Ordering::Greater => {
+                    let (path, value) = self.after.next().unwrap();
+                    return Some(TrieChange::Added(path, value));
+                },
+
+                std::cmp::Ordering::Equal => {
+                    let (path, before) = self.before.next().unwrap();
+                    let (_, after) = self.after.next().unwrap();
+
+                    if before != after {
+                        return Some(TrieChange::Changed(path, before, after));
+                    }
+                },
+            }
+        }
     }
 }
 
@@ -800,6 +1882,332 @@ mod tests {
         assert_eq!(Path::new().with_join(&Path::from("bin")), Path::from("bin"));
     }
 
+    #[test]
+    fn test_fs_write_atomic() {
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        file.fs_write_atomic("hello").unwrap();
+        assert_eq!(file.fs_read_text().unwrap(), "hello");
+
+        file.fs_write_atomic("world").unwrap();
+        assert_eq!(file.fs_read_text().unwrap(), "world");
+
+        assert_eq!(dir.fs_read_dir().unwrap().count(), 1);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_write_atomic_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("file.sh");
+
+        file.fs_write_atomic_with("#!/bin/sh", &AtomicWriteOptions {
+            permissions: Some(fs::Permissions::from_mode(0o755)),
+            ..AtomicWriteOptions::default()
+        }).unwrap();
+
+        file.fs_write_atomic("#!/bin/bash").unwrap();
+
+        assert_eq!(file.fs_metadata().unwrap().permissions().mode() & 0o777, 0o755);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_change_atomic() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        let outcome = file.fs_change_atomic("hello", fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::Created {bytes_written: 5});
+        assert_eq!(file.fs_read_text().unwrap(), "hello");
+
+        let outcome = file.fs_change_atomic("hello", fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::PermissionsUpdated);
+        assert_eq!(file.fs_metadata().unwrap().permissions().mode() & 0o777, 0o600);
+
+        let outcome = file.fs_change_atomic("world!", fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::ContentUpdated {previous_size: 5, bytes_written: 6});
+        assert_eq!(file.fs_read_text().unwrap(), "world!");
+        assert_eq!(file.fs_metadata().unwrap().permissions().mode() & 0o777, 0o600);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_links() {
+        let dir = Path::temp_dir().unwrap();
+
+        let file = dir.with_join_str("pkg/index.js");
+        file.fs_create_parent().unwrap();
+        file.fs_write("module.exports = 42;").unwrap();
+
+        let link = dir.with_join_str("node_modules/pkg");
+        link.fs_create_parent().unwrap();
+        link.fs_symlink_relative(&dir.with_join_str("pkg")).unwrap();
+
+        assert!(link.fs_is_symlink());
+        assert!(link.fs_is_dir());
+        assert!(!file.fs_is_symlink());
+        assert!(link.fs_symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(link.fs_read_link().unwrap(), Path::from("../pkg"));
+        assert_eq!(link.with_join_str("index.js").fs_read_text().unwrap(), "module.exports = 42;");
+
+        let hardlink = dir.with_join_str("hardlink.js");
+        hardlink.fs_hardlink(&file).unwrap();
+
+        assert_eq!(file.fs_link_count().unwrap(), 2);
+        assert_eq!(hardlink.fs_read_text().unwrap(), "module.exports = 42;");
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_relative_link_target() {
+        assert_eq!(Path::from("/a/node_modules/b").relative_link_target(&Path::from("/a/packages/b")), Some(Path::from("../packages/b")));
+        assert_eq!(Path::from("/a/b").relative_link_target(&Path::from("/a/c")), Some(Path::from("c")));
+        assert_eq!(Path::from("/a").relative_link_target(&Path::from("/b/c")), Some(Path::from("b/c")));
+
+        assert_eq!(Path::from("rel/link").relative_link_target(&Path::from("/a")), None);
+        assert_eq!(Path::from("/a").relative_link_target(&Path::from("rel/target")), None);
+
+        let err = Path::from("rel/link").fs_symlink_relative(&Path::from("rel/target")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_fs_read_dir_entries() {
+        let dir = Path::temp_dir().unwrap();
+
+        dir.with_join_str("b").fs_write("b").unwrap();
+        dir.with_join_str("a").fs_create_dir_all().unwrap();
+
+        let entries = dir.fs_read_dir_entries_sorted().unwrap();
+
+        assert_eq!(entries.iter().map(|(path, _)| path.clone()).collect::<Vec<_>>(), vec![
+            dir.with_join_str("a"),
+            dir.with_join_str("b"),
+        ]);
+
+        assert!(entries[0].1.is_dir());
+        assert!(entries[1].1.is_file());
+
+        let mut names = dir.fs_read_dir_names().unwrap();
+        names.sort();
+
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(dir.fs_read_dir_entries().unwrap().len(), 2);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_change_outcome() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        let outcome = file.fs_change("hello", fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::Created {bytes_written: 5});
+        assert_eq!(outcome.bytes_written(), 5);
+
+        let outcome = file.fs_change("hello", fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::Unchanged);
+        assert!(!outcome.is_changed());
+
+        let outcome = file.fs_change("hi", fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::ContentUpdated {previous_size: 5, bytes_written: 2});
+
+        let outcome = file.fs_change("hi", fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::PermissionsUpdated);
+        assert_eq!(outcome.bytes_written(), 0);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_change_from_reader() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        let outcome = file.fs_change_from_reader(&b"hello"[..], fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::Created {bytes_written: 5});
+
+        let outcome = file.fs_change_from_reader(&b"hello"[..], fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::Unchanged);
+
+        let outcome = file.fs_change_from_reader(&b"hell"[..], fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::ContentUpdated {previous_size: 5, bytes_written: 4});
+        assert_eq!(file.fs_read_text().unwrap(), "hell");
+
+        let outcome = file.fs_change_from_reader(&b"hell"[..], fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::PermissionsUpdated);
+
+        assert_eq!(dir.fs_read_dir().unwrap().count(), 1);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[derive(Default)]
+    struct SumHasher(u8);
+
+    impl ContentHasher for SumHasher {
+        fn update(&mut self, data: &[u8]) {
+            self.0 = data.iter().fold(self.0, |acc, b| acc.wrapping_add(*b));
+        }
+
+        fn finalize(self) -> Vec<u8> {
+            vec![self.0]
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_change_with_digest() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        file.fs_write("ab").unwrap();
+        file.fs_set_permissions(fs::Permissions::from_mode(0o644)).unwrap();
+
+        let digest = [b'a'.wrapping_add(b'b')];
+
+        // The reader isn't consumed when the digest matches
+        let outcome = file.fs_change_with_digest(&digest, SumHasher::default(), &b"unused"[..], fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::Unchanged);
+        assert_eq!(file.fs_read_text().unwrap(), "ab");
+
+        let outcome = file.fs_change_with_digest(&[0], SumHasher::default(), &b"abc"[..], fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(outcome, ChangeOutcome::ContentUpdated {previous_size: 2, bytes_written: 3});
+        assert_eq!(file.fs_read_text().unwrap(), "abc");
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_expect_streaming() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        file.fs_change("hello\nworld\n", fs::Permissions::from_mode(0o644)).unwrap();
+
+        assert!(file.fs_expect_from_reader(&b"hello\nworld\n"[..], fs::Permissions::from_mode(0o644)).is_ok());
+
+        match file.fs_expect_from_reader(&b"hello\nthere\nfriend\n"[..], fs::Permissions::from_mode(0o644)).unwrap_err() {
+            ImmutableErr::ContentMismatch {diff, ..} => {
+                assert_eq!(diff.expected_size, Some(19));
+                assert_eq!(diff.actual_size, 12);
+                assert_eq!(diff.offset, 6);
+                assert_eq!(diff.line, 2);
+            },
+
+            err => panic!("Unexpected error: {}", err),
+        }
+
+        // The rest of a large reader isn't read once the difference is found
+        let large = vec![b'x'; compare::CHUNK_SIZE * 4];
+        let mut reader = compare::CountingReader {inner: &large[..], count: 0, eof: false};
+
+        match file.fs_expect_from_reader(&mut reader, fs::Permissions::from_mode(0o644)).unwrap_err() {
+            ImmutableErr::ContentMismatch {diff, ..} => {
+                assert_eq!(diff.expected_size, None);
+                assert_eq!(diff.offset, 0);
+            },
+
+            err => panic!("Unexpected error: {}", err),
+        }
+
+        assert_eq!(reader.count, compare::CHUNK_SIZE as u64);
+
+        let mut expected = SumHasher::default();
+        expected.update(b"hello\nworld\n");
+
+        assert!(file.fs_expect_digest(&expected.finalize(), SumHasher::default(), fs::Permissions::from_mode(0o644)).is_ok());
+        assert!(matches!(file.fs_expect_digest(&[0], SumHasher::default(), fs::Permissions::from_mode(0o644)), Err(ImmutableErr::DigestMismatch {..})));
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fs_expect() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("file.txt");
+
+        let err = file.fs_expect("hello\n", fs::Permissions::from_mode(0o644)).unwrap_err();
+        assert!(matches!(err, ImmutableErr::Missing {ref path} if path == &file));
+
+        file.fs_change("hello\nworld\n", fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(file.fs_expect("hello\nworld\n", fs::Permissions::from_mode(0o644)).is_ok());
+
+        match file.fs_expect_with_diff("hello\nthere\n", fs::Permissions::from_mode(0o644)).unwrap_err() {
+            ImmutableErr::ContentMismatch {path, diff} => {
+                assert_eq!(path, file);
+                assert_eq!(diff.expected_size, Some(12));
+                assert_eq!(diff.actual_size, 12);
+                assert_eq!(diff.offset, 6);
+                assert_eq!(diff.line, 2);
+                assert!(diff.unified_diff.unwrap().contains("-world\n+there\n"));
+            },
+
+            err => panic!("Unexpected error: {}", err),
+        }
+
+        // Sizes differ: the first difference is still located
+        match file.fs_expect("hello\nworld\n!", fs::Permissions::from_mode(0o644)).unwrap_err() {
+            ImmutableErr::ContentMismatch {diff, ..} => {
+                assert_eq!(diff.expected_size, Some(13));
+                assert_eq!(diff.actual_size, 12);
+                assert_eq!(diff.offset, 12);
+                assert_eq!(diff.line, 3);
+            },
+
+            err => panic!("Unexpected error: {}", err),
+        }
+
+        match file.fs_expect_with_diff("hello\nworld\n!", fs::Permissions::from_mode(0o644)).unwrap_err() {
+            ImmutableErr::ContentMismatch {diff, ..} => {
+                assert_eq!(diff.offset, 12);
+                assert_eq!(diff.line, 3);
+            },
+
+            err => panic!("Unexpected error: {}", err),
+        }
+
+        match file.fs_expect("hello\nworld\n", fs::Permissions::from_mode(0o755)).unwrap_err() {
+            ImmutableErr::PermissionsMismatch {path, expected, actual} => {
+                assert_eq!(path, file);
+                assert_eq!(expected.mode() & 0o777, 0o755);
+                assert_eq!(actual.mode() & 0o777, 0o644);
+            },
+
+            err => panic!("Unexpected error: {}", err),
+        }
+
+        dir.fs_rm().unwrap();
+    }
+
     #[test]
     fn test_resolve_path() {
         assert_eq!(resolve_path("/a/b/c/./../d/"), "/a/b/d/");
@@ -1071,6 +2479,173 @@ mod tests {
         assert_eq!(trie.get_ancestor_value(&ancestor_path).unwrap(), item);
     }
 
+    #[test]
+    fn test_trie_shared_prefixes() {
+        let mut trie = Trie::default();
+        trie.insert(Path::from("/path/to/item-a"), "a");
+        trie.insert(Path::from("/path/to/item-b"), "b");
+        trie.insert(Path::from("/path/to/item"), "item");
+        trie.insert(Path::from("/path"), "path");
+
+        assert_eq!(trie.len(), 4);
+        assert_eq!(trie.get(&Path::from("/path/to/item-a")), Some(&"a"));
+        assert_eq!(trie.get(&Path::from("/path/to/item-b")), Some(&"b"));
+        assert_eq!(trie.get(&Path::from("/path/to/item")), Some(&"item"));
+        assert_eq!(trie.get(&Path::from("/path")), Some(&"path"));
+        assert_eq!(trie.get(&Path::from("/path/to")), None);
+        assert_eq!(trie.get(&Path::from("/path/to/item-")), None);
+
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path/to/item-c")), Some(&"path"));
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path/to/item/child")), Some(&"item"));
+    }
+
+    #[test]
+    fn test_trie_root() {
+        let mut trie = Trie::default();
+        trie.insert(Path::root(), "root");
+        trie.insert(Path::from("/usr"), "usr");
+
+        assert_eq!(trie.get(&Path::root()), Some(&"root"));
+        assert_eq!(trie.get_ancestor_value(&Path::from("/var/log")), Some(&"root"));
+        assert_eq!(trie.get_ancestor_value(&Path::from("/usr/bin")), Some(&"usr"));
+        assert_eq!(trie.get_ancestor_value(&Path::from("usr/bin")), None);
+    }
+
+    #[test]
+    fn test_trie_relative() {
+        let mut trie = Trie::default();
+        trie.insert(Path::from("foo/bar"), 1);
+
+        assert_eq!(trie.get(&Path::from("foo/bar")), Some(&1));
+        assert_eq!(trie.get(&Path::from("/foo/bar")), None);
+        assert_eq!(trie.get_ancestor_value(&Path::from("foo/bar/baz")), Some(&1));
+    }
+
+    #[test]
+    fn test_trie_get_mut() {
+        let mut trie = Trie::default();
+        trie.insert(Path::from("/path/to/item"), 1);
+
+        *trie.get_mut(&Path::from("/path/to/item")).unwrap() += 1;
+
+        assert_eq!(trie.get(&Path::from("/path/to/item")), Some(&2));
+        assert_eq!(trie.get_mut(&Path::from("/path/to")), None);
+    }
+
+    #[test]
+    fn test_trie_remove_compacts() {
+        let mut trie = Trie::default();
+        trie.insert(Path::from("/path/to/item-a"), "a");
+        trie.insert(Path::from("/path/to/item-b"), "b");
+
+        trie.remove(&Path::from("/path/to/item-a"));
+        trie.remove(&Path::from("/path/to/nope"));
+
+        assert_eq!(trie.len(), 1);
+        assert_eq!(trie.get(&Path::from("/path/to/item-b")), Some(&"b"));
+        assert_eq!(trie.root.children.len(), 1);
+        assert_eq!(trie.root.children[0].label(), "/path/to/item-b/");
+
+        trie.remove(&Path::from("/path/to/item-b"));
+
+        assert!(trie.is_empty());
+        assert!(trie.root.children.is_empty());
+    }
+
+    #[test]
+    fn test_trie_node_paths() {
+        fn check<T>(node: &TrieNode<T>) {
+            for child in &node.children {
+                assert_eq!(child.label_start, node.path.path.len());
+                assert!(child.path.as_str().starts_with(node.path.as_str()));
+                assert!(!child.label().is_empty() && child.label().ends_with('/'));
+
+                check(child);
+            }
+        }
+
+        let mut trie = Trie::default();
+
+        for key in ["/a/b/c", "/a/b/d", "/a/e", "/a", "/f/g", "/a/b/c/h", "rel/i"] {
+            trie.insert(Path::from(key), key);
+            check(&trie.root);
+        }
+
+        for key in ["/a/b/d", "/a", "/a/b/c", "/nope"] {
+            trie.remove(&Path::from(key));
+            check(&trie.root);
+        }
+
+        let entries = trie.iter()
+            .map(|(path, value)| (path.as_str(), *value))
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries, vec![("/a/b/c/h/", "/a/b/c/h"), ("/a/e/", "/a/e"), ("/f/g/", "/f/g"), ("rel/i/", "rel/i")]);
+        assert_eq!(trie.get_ancestor_path(&Path::from("/a/b/c/h/j")), Some(&Path::from("/a/b/c/h/")));
+    }
+
+    #[test]
+    fn test_trie_iter() {
+        let mut trie = Trie::default();
+        trie.insert(Path::from("/b"), 2);
+        trie.insert(Path::from("/a/c"), 3);
+        trie.insert(Path::from("/a-b"), 4);
+        trie.insert(Path::from("/a"), 1);
+
+        let entries = trie.iter()
+            .map(|(p, v)| (p.as_str(), *v))
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries, vec![("/a-b/", 4), ("/a/", 1), ("/a/c/", 3), ("/b/", 2)]);
+    }
+
+    #[test]
+    fn test_trie_diff() {
+        let mut before = Trie::default();
+        before.insert(Path::from("/a"), 1);
+        before.insert(Path::from("/b"), 2);
+        before.insert(Path::from("/c"), 3);
+
+        let mut after = Trie::default();
+        after.insert(Path::from("/b"), 2);
+        after.insert(Path::from("/c"), 4);
+        after.insert(Path::from("/d"), 5);
+
+        let changes = before.diff(&after).collect::<Vec<_>>();
+
+        assert_eq!(changes, vec![
+            TrieChange::Removed(&Path::from("/a/"), &1),
+            TrieChange::Changed(&Path::from("/c/"), &3, &4),
+            TrieChange::Added(&Path::from("/d/"), &5),
+        ]);
+    }
+
+    #[test]
+    fn test_trie_diff_identical() {
+        let mut trie = Trie::default();
+        trie.insert(Path::from("/a"), 1);
+
+        assert_eq!(trie.diff(&trie.clone()).count(), 0);
+    }
+
+    #[test]
+    fn test_trie_merge() {
+        let mut a = Trie::default();
+        a.insert(Path::from("/a"), 1);
+        a.insert(Path::from("/b"), 2);
+
+        let mut b = Trie::default();
+        b.insert(Path::from("/b"), 10);
+        b.insert(Path::from("/c"), 3);
+
+        let merged = a.merge(&b, |_, a, b| a + b);
+
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged.get(&Path::from("/a")), Some(&1));
+        assert_eq!(merged.get(&Path::from("/b")), Some(&12));
+        assert_eq!(merged.get(&Path::from("/c")), Some(&3));
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn test_serde_serialization() {
@@ -0,0 +1,394 @@
+use std::io::{Read, Seek, Write};
+use std::time::{Duration, Instant};
+use std::{fs, io};
+
+use crate::Path;
+
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long a lockfile may stay without a valid PID before being considered
+// as left behind by a process that crashed while creating it
+const LOCKFILE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+
+    #[default]
+    Exclusive,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LockOptions {
+    pub kind: LockKind,
+
+    /// How long to wait for the lock before failing with `TimedOut`. `None`
+    /// waits forever, and a zero duration fails with `WouldBlock` if the
+    /// lock is already held.
+    pub timeout: Option<Duration>,
+
+    /// Rather than using `flock`, create the file exclusively and write the
+    /// current PID into it. Locks left by dead processes are recovered. Only
+    /// exclusive locks are supported in this mode.
+    pub lockfile: bool,
+}
+
+/// Guard returned by `Path::fs_lock_exclusive` and friends. The lock is
+/// released when the guard is dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    path: Path,
+
+    // None in lockfile mode
+    file: Option<fs::File>,
+    released: bool,
+}
+
+impl FileLock {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn unlock(mut self) -> io::Result<()> {
+        self.release()
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        self.released = true;
+
+        match self.file.take() {
+            Some(file) => unlock_file(&file),
+            None => fs::remove_file(self.path.to_path_buf()),
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.release();
+        }
+    }
+}
+
+pub(crate) fn lock(path: &Path, options: &LockOptions) -> io::Result<FileLock> {
+    if options.lockfile {
+        if options.kind == LockKind::Shared {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Lockfiles only support exclusive locks"));
+        }
+
+        return poll(options.timeout, || try_lockfile(path));
+    }
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path.to_path_buf())?;
+
+    if options.timeout.is_none() {
+        lock_file(&file, options.kind, true)?;
+    } else {
+        poll(options.timeout, || lock_file(&file, options.kind, false))?;
+    }
+
+    Ok(FileLock {path: path.clone(), file: Some(file), released: false})
+}
+
+/// Calls `attempt` until it returns a value or the timeout expires.
+fn poll<T, F: FnMut() -> io::Result<Option<T>>>(timeout: Option<Duration>, mut attempt: F) -> io::Result<T> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut interval = Duration::from_millis(1);
+
+    loop {
+        if let Some(value) = attempt()? {
+            return Ok(value);
+        }
+
+        let remaining = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => MAX_POLL_INTERVAL,
+        };
+
+        if remaining.is_zero() {
+            return match timeout {
+                Some(timeout) if timeout.is_zero() => Err(io::Error::new(io::ErrorKind::WouldBlock, "The lock is held by someone else")),
+                _ => Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out waiting for the lock")),
+            };
+        }
+
+        std::thread::sleep(interval.min(remaining));
+        interval = (interval * 2).min(MAX_POLL_INTERVAL);
+    }
+}
+
+#[cfg(unix)]
+fn lock_file(file: &fs::File, kind: LockKind, blocking: bool) -> io::Result<Option<()>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut operation = match kind {
+        LockKind::Shared => libc::LOCK_SH,
+        LockKind::Exclusive => libc::LOCK_EX,
+    };
+
+    if !blocking {
+        operation |= libc::LOCK_NB;
+    }
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(Some(()));
+        }
+
+        let err = io::Error::last_os_error();
+
+        match err.kind() {
+            io::ErrorKind::Interrupted => continue,
+            io::ErrorKind::WouldBlock if !blocking => return Ok(None),
+            _ => return Err(err),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unlock_file(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    match unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_file(_file: &fs::File, _kind: LockKind, _blocking: bool) -> io::Result<Option<()>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "File locks aren't supported on this platform"))
+}
+
+#[cfg(not(unix))]
+fn unlock_file(_file: &fs::File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+
+    match unsafe { libc::kill(pid, 0) } {
+        0 => true,
+        _ => io::Error::last_os_error().raw_os_error() == Some(libc::EPERM),
+    }
+}
+
+// Without a way to check, we never consider a lock as stale
+#[cfg(not(unix))]
+fn is_process_alive(_pid: u32) -> bool {
+    true
+}
+
+fn is_lockfile_stale(file: &mut fs::File) -> io::Result<bool> {
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+
+    match content.trim().parse() {
+        Ok(pid) => Ok(!is_process_alive(pid)),
+
+        // Lockfiles without a valid PID may be in the process of being written
+        Err(_) => {
+            let age = file.metadata()?.modified()?.elapsed().unwrap_or_default();
+            Ok(age > LOCKFILE_GRACE_PERIOD)
+        },
+    }
+}
+
+/// Removes the stale lockfile that `file` was opened from. Returns false if
+/// another process is recovering it, or if it turned out not to be stale.
+#[cfg(unix)]
+fn recover_lockfile(path: &Path, mut file: fs::File) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    // Other processes may be recovering the same lockfile; holding the flock
+    // ensures only one of them removes it, and checking that the path still
+    // points to the locked file ensures we never delete a recreated lockfile.
+    if lock_file(&file, LockKind::Exclusive, false)?.is_none() {
+        return Ok(false);
+    }
+
+    let current = match fs::metadata(path.to_path_buf()) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(err) => return Err(err),
+    };
+
+    let locked = file.metadata()?;
+
+    if (current.dev(), current.ino()) != (locked.dev(), locked.ino()) {
+        return Ok(true);
+    }
+
+    file.rewind()?;
+
+    if !is_lockfile_stale(&mut file)? {
+        return Ok(false);
+    }
+
+    match fs::remove_file(path.to_path_buf()) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+// Without flock, stale lockfiles can't be removed safely
+#[cfg(not(unix))]
+fn recover_lockfile(_path: &Path, _file: fs::File) -> io::Result<bool> {
+    Ok(false)
+}
+
+fn write_lockfile_pid(mut file: fs::File) -> io::Result<()> {
+    file.write_all(format!("{}\n", std::process::id()).as_bytes())?;
+    file.sync_all()
+}
+
+fn try_lockfile(path: &Path) -> io::Result<Option<FileLock>> {
+    let create = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path.to_path_buf());
+
+    match create {
+        Ok(file) => {
+            // A lockfile without a PID would block everyone else until it
+            // gets old enough to be considered stale
+            if let Err(err) = write_lockfile_pid(file) {
+                let _ = fs::remove_file(path.to_path_buf());
+                return Err(err);
+            }
+
+            return Ok(Some(FileLock {path: path.clone(), file: None, released: false}));
+        },
+
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {},
+        Err(err) => return Err(err),
+    }
+
+    let mut file = match fs::File::open(path.to_path_buf()) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return try_lockfile(path),
+        Err(err) => return Err(err),
+    };
+
+    if !is_lockfile_stale(&mut file)? || !recover_lockfile(path, file)? {
+        return Ok(None);
+    }
+
+    try_lockfile(path)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_exclusive() {
+        let dir = Path::temp_dir().unwrap();
+        let path = dir.with_join_str("lock");
+
+        let guard = path.fs_lock_exclusive().unwrap();
+
+        assert!(path.fs_try_lock_exclusive().unwrap().is_none());
+        assert!(path.fs_try_lock_shared().unwrap().is_none());
+
+        let err = path.fs_lock_with(&LockOptions {timeout: Some(Duration::from_millis(20)), ..LockOptions::default()}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        drop(guard);
+
+        assert!(path.fs_try_lock_exclusive().unwrap().is_some());
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_lock_shared() {
+        let dir = Path::temp_dir().unwrap();
+        let path = dir.with_join_str("lock");
+
+        let first = path.fs_lock_shared().unwrap();
+        let second = path.fs_try_lock_shared().unwrap();
+
+        assert!(second.is_some());
+        assert!(path.fs_try_lock_exclusive().unwrap().is_none());
+
+        first.unlock().unwrap();
+        drop(second);
+
+        assert!(path.fs_try_lock_exclusive().unwrap().is_some());
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_lockfile() {
+        let dir = Path::temp_dir().unwrap();
+        let path = dir.with_join_str("lock");
+
+        let options = LockOptions {lockfile: true, timeout: Some(Duration::ZERO), ..LockOptions::default()};
+
+        let guard = path.fs_lock_with(&options).unwrap();
+        assert_eq!(path.fs_read_text().unwrap(), format!("{}\n", std::process::id()));
+
+        let err = path.fs_lock_with(&options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        drop(guard);
+        assert!(!path.fs_exists());
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_lockfile_stale() {
+        let dir = Path::temp_dir().unwrap();
+        let path = dir.with_join_str("lock");
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+
+        path.fs_write(format!("{}\n", child.id())).unwrap();
+
+        let options = LockOptions {lockfile: true, timeout: Some(Duration::from_secs(1)), ..LockOptions::default()};
+        let _guard = path.fs_lock_with(&options).unwrap();
+
+        assert_eq!(path.fs_read_text().unwrap(), format!("{}\n", std::process::id()));
+        assert_eq!(dir.fs_read_dir_names().unwrap(), vec!["lock"]);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_lockfile_without_pid() {
+        let dir = Path::temp_dir().unwrap();
+        let path = dir.with_join_str("lock");
+
+        let options = LockOptions {lockfile: true, timeout: Some(Duration::ZERO), ..LockOptions::default()};
+
+        // A recent lockfile may still be being written
+        path.fs_write("garbage").unwrap();
+
+        let err = path.fs_lock_with(&options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        let file = fs::File::options().write(true).open(path.to_path_buf()).unwrap();
+        file.set_modified(std::time::SystemTime::now() - LOCKFILE_GRACE_PERIOD * 2).unwrap();
+        drop(file);
+
+        let _guard = path.fs_lock_with(&options).unwrap();
+        assert_eq!(path.fs_read_text().unwrap(), format!("{}\n", std::process::id()));
+
+        dir.fs_rm().unwrap();
+    }
+}
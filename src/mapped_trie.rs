@@ -0,0 +1,379 @@
+use std::io;
+use std::marker::PhantomData;
+
+use crate::{Path, Trie};
+
+const MAGIC: &[u8; 8] = b"ARCATRIE";
+const VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 16;
+const INDEX_ENTRY_SIZE: usize = 16;
+
+/// A readonly trie stored in a compact binary format that can be queried
+/// directly from its serialized representation (typically a memory-mapped
+/// file), without having to deserialize it first.
+///
+/// The format is made of a header (magic, version, entry count), followed by
+/// an index of fixed-size records sorted by key, followed by a blob holding
+/// the keys and the bincode-encoded values. All integers are little-endian.
+pub struct MappedTrie<T, B = memmap2::Mmap> {
+    data: B,
+    count: usize,
+    phantom: PhantomData<T>,
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn trie_key(key: &Path) -> String {
+    let mut p = key.to_string();
+
+    if !p.ends_with('/') {
+        p.push('/');
+    }
+
+    p
+}
+
+impl<T: bincode::Encode> MappedTrie<T> {
+    pub fn encode(trie: &Trie<T>) -> io::Result<Vec<u8>> {
+        let mut entries = trie.iter()
+            .map(|(path, value)| {
+                let value = bincode::encode_to_vec(value, bincode::config::standard())
+                    .map_err(invalid_data)?;
+
+                Ok((path.as_str(), value))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let count = u32::try_from(entries.len())
+            .map_err(invalid_data)?;
+
+        let mut index = Vec::with_capacity(entries.len() * INDEX_ENTRY_SIZE);
+        let mut blob = Vec::new();
+
+        for (key, value) in entries {
+            for bytes in [key.as_bytes(), value.as_slice()] {
+                let offset = u32::try_from(blob.len()).map_err(invalid_data)?;
+                let len = u32::try_from(bytes.len()).map_err(invalid_data)?;
+
+                index.extend_from_slice(&offset.to_le_bytes());
+                index.extend_from_slice(&len.to_le_bytes());
+
+                blob.extend_from_slice(bytes);
+            }
+        }
+
+        u32::try_from(blob.len())
+            .map_err(invalid_data)?;
+
+        let mut data = Vec::with_capacity(HEADER_SIZE + index.len() + blob.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+        data.extend_from_slice(&index);
+        data.extend_from_slice(&blob);
+
+        Ok(data)
+    }
+
+    /// Writes the encoded trie to a new file renamed over `path`, so that
+    /// readers which already mapped the previous file keep a valid mapping.
+    pub fn write(trie: &Trie<T>, path: &Path) -> io::Result<()> {
+        path.fs_write_atomic(Self::encode(trie)?)?;
+        Ok(())
+    }
+}
+
+impl<T> MappedTrie<T> {
+    /// Memory-maps the given file and validates its header.
+    ///
+    /// The file must not be modified while it's mapped; doing so is undefined
+    /// behaviour. Writers should create a new file and rename it in place.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = std::fs::File::open(path.to_path_buf())?;
+        let data = unsafe { memmap2::Mmap::map(&file)? };
+
+        Self::from_bytes(data)
+    }
+}
+
+impl<T, B: AsRef<[u8]>> MappedTrie<T, B> {
+    pub fn from_bytes(data: B) -> io::Result<Self> {
+        let bytes = data.as_ref();
+
+        if bytes.len() < HEADER_SIZE || &bytes[0..8] != MAGIC {
+            return Err(invalid_data("Invalid trie file signature"));
+        }
+
+        let version = read_u32(bytes, 8);
+        if version != VERSION {
+            return Err(invalid_data(format!("Unsupported trie file version ({})", version)));
+        }
+
+        let count = read_u32(bytes, 12) as usize;
+
+        let blob_offset = count.checked_mul(INDEX_ENTRY_SIZE)
+            .and_then(|index_size| index_size.checked_add(HEADER_SIZE))
+            .filter(|blob_offset| *blob_offset <= bytes.len())
+            .ok_or_else(|| invalid_data("Truncated trie file index"))?;
+
+        let blob_size = bytes.len() - blob_offset;
+
+        for n in 0..count {
+            let record = HEADER_SIZE + n * INDEX_ENTRY_SIZE;
+
+            for field in [record, record + 8] {
+                let offset = read_u32(bytes, field) as usize;
+                let len = read_u32(bytes, field + 4) as usize;
+
+                if offset + len > blob_size {
+                    return Err(invalid_data("Out of bounds trie file record"));
+                }
+            }
+        }
+
+        Ok(MappedTrie {
+            data,
+            count,
+            phantom: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn slice(&self, field: usize) -> &[u8] {
+        let bytes = self.data.as_ref();
+        let blob_offset = HEADER_SIZE + self.count * INDEX_ENTRY_SIZE;
+
+        let offset = read_u32(bytes, field) as usize;
+        let len = read_u32(bytes, field + 4) as usize;
+
+        &bytes[blob_offset + offset..blob_offset + offset + len]
+    }
+
+    fn key_at(&self, n: usize) -> &[u8] {
+        self.slice(HEADER_SIZE + n * INDEX_ENTRY_SIZE)
+    }
+
+    fn value_at(&self, n: usize) -> &[u8] {
+        self.slice(HEADER_SIZE + n * INDEX_ENTRY_SIZE + 8)
+    }
+
+    fn lower_bound(&self, key: &[u8]) -> usize {
+        let mut low = 0;
+        let mut high = self.count;
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+
+            if self.key_at(mid) < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        low
+    }
+
+    fn find(&self, key: &[u8]) -> Option<usize> {
+        let n = self.lower_bound(key);
+
+        if n < self.count && self.key_at(n) == key {
+            Some(n)
+        } else {
+            None
+        }
+    }
+
+    fn find_ancestor(&self, key: &Path) -> Option<usize> {
+        let k = trie_key(key);
+
+        k.match_indices('/')
+            .rev()
+            .find_map(|(i, _)| self.find(&k.as_bytes()[..=i]))
+    }
+
+    /// Returns the raw bincode-encoded value stored for the given key.
+    pub fn get_raw(&self, key: &Path) -> Option<&[u8]> {
+        self.find(trie_key(key).as_bytes())
+            .map(|n| self.value_at(n))
+    }
+
+    pub fn get_ancestor_path(&self, key: &Path) -> io::Result<Option<Path>> {
+        self.find_ancestor(key)
+            .map(|n| self.path_at(n))
+            .transpose()
+    }
+
+    fn path_at(&self, n: usize) -> io::Result<Path> {
+        let key = std::str::from_utf8(self.key_at(n))
+            .map_err(invalid_data)?;
+
+        Ok(Path::from(key))
+    }
+}
+
+impl<T: bincode::Decode<()>, B: AsRef<[u8]>> MappedTrie<T, B> {
+    fn decode(&self, n: usize) -> io::Result<T> {
+        let (value, _) = bincode::decode_from_slice(self.value_at(n), bincode::config::standard())
+            .map_err(invalid_data)?;
+
+        Ok(value)
+    }
+
+    pub fn get(&self, key: &Path) -> io::Result<Option<T>> {
+        self.find(trie_key(key).as_bytes())
+            .map(|n| self.decode(n))
+            .transpose()
+    }
+
+    pub fn get_ancestor_value(&self, key: &Path) -> io::Result<Option<T>> {
+        self.find_ancestor(key)
+            .map(|n| self.decode(n))
+            .transpose()
+    }
+
+    /// Iterates over all the entries located at or below the given key,
+    /// sorted by key.
+    pub fn descendants(&self, key: &Path) -> MappedTrieDescendants<'_, T, B> {
+        let prefix = trie_key(key);
+        let next = self.lower_bound(prefix.as_bytes());
+
+        MappedTrieDescendants {
+            trie: self,
+            prefix,
+            next,
+        }
+    }
+}
+
+pub struct MappedTrieDescendants<'a, T, B> {
+    trie: &'a MappedTrie<T, B>,
+    prefix: String,
+    next: usize,
+}
+
+impl<'a, T: bincode::Decode<()>, B: AsRef<[u8]>> Iterator for MappedTrieDescendants<'a, T, B> {
+    type Item = io::Result<(Path, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.trie.count || !self.trie.key_at(self.next).starts_with(self.prefix.as_bytes()) {
+            return None;
+        }
+
+        let n = self.next;
+        self.next += 1;
+
+        Some(self.trie.path_at(n).and_then(|path| {
+            Ok((path, self.trie.decode(n)?))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_trie() -> Trie<String> {
+        let mut trie = Trie::default();
+        trie.insert(Path::from("/path/to/"), "parent".to_string());
+        trie.insert(Path::from("/path/to/item/"), "item".to_string());
+        trie.insert(Path::from("/path/to/items/"), "items".to_string());
+        trie.insert(Path::from("/other/"), "other".to_string());
+        trie
+    }
+
+    fn make_mapped_trie() -> MappedTrie<String, Vec<u8>> {
+        let bytes = MappedTrie::encode(&make_trie()).unwrap();
+        MappedTrie::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_mapped_trie_get() {
+        let trie = make_mapped_trie();
+
+        assert_eq!(trie.len(), 4);
+        assert_eq!(trie.get(&Path::from("/path/to/item")).unwrap(), Some("item".to_string()));
+        assert_eq!(trie.get(&Path::from("/path/to/item/")).unwrap(), Some("item".to_string()));
+        assert_eq!(trie.get(&Path::from("/path/to/nope")).unwrap(), None);
+        assert_eq!(trie.get(&Path::from("/path")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_mapped_trie_get_ancestor_value() {
+        let trie = make_mapped_trie();
+
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path/to/item/child")).unwrap(), Some("item".to_string()));
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path/to/ite")).unwrap(), Some("parent".to_string()));
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path")).unwrap(), None);
+        assert_eq!(trie.get_ancestor_path(&Path::from("/other/foo")).unwrap(), Some(Path::from("/other/")));
+    }
+
+    #[test]
+    fn test_mapped_trie_descendants() {
+        let trie = make_mapped_trie();
+
+        let descendants = trie.descendants(&Path::from("/path/to"))
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(descendants, vec![
+            (Path::from("/path/to/"), "parent".to_string()),
+            (Path::from("/path/to/item/"), "item".to_string()),
+            (Path::from("/path/to/items/"), "items".to_string()),
+        ]);
+
+        assert_eq!(trie.descendants(&Path::from("/path/to/item")).count(), 1);
+        assert_eq!(trie.descendants(&Path::from("/nope")).count(), 0);
+    }
+
+    #[test]
+    fn test_mapped_trie_invalid() {
+        assert!(MappedTrie::<String, _>::from_bytes(b"nope".to_vec()).is_err());
+
+        let mut bytes = MappedTrie::encode(&make_trie()).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(MappedTrie::<String, _>::from_bytes(bytes).is_err());
+
+        let mut bytes = MappedTrie::encode(&make_trie()).unwrap();
+        bytes[8] = 42;
+        assert!(MappedTrie::<String, _>::from_bytes(bytes).is_err());
+    }
+
+    #[test]
+    fn test_mapped_trie_open() {
+        let dir = Path::temp_dir().unwrap();
+        let file = dir.with_join_str("trie.bin");
+
+        MappedTrie::write(&make_trie(), &file).unwrap();
+
+        let trie = MappedTrie::<String>::open(&file).unwrap();
+        assert_eq!(trie.get(&Path::from("/other")).unwrap(), Some("other".to_string()));
+
+        // Rewriting the file doesn't affect the existing mapping
+        let mut updated = Trie::default();
+        updated.insert(Path::from("/other/"), "updated".to_string());
+        MappedTrie::write(&updated, &file).unwrap();
+
+        assert_eq!(trie.len(), 4);
+        assert_eq!(trie.get(&Path::from("/other")).unwrap(), Some("other".to_string()));
+        assert_eq!(MappedTrie::<String>::open(&file).unwrap().get(&Path::from("/other")).unwrap(), Some("updated".to_string()));
+
+        dir.fs_rm().unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{Path, Trie, TrieIter};
+
+/// A trie where each key holds a list of values rather than a single one.
+#[derive(Debug, Clone)]
+pub struct MultiTrie<T> {
+    inner: Trie<Vec<T>>,
+}
+
+impl<T> Default for MultiTrie<T> {
+    fn default() -> Self {
+        MultiTrie {
+            inner: Trie::default(),
+        }
+    }
+}
+
+impl<T> MultiTrie<T> {
+    pub fn new() -> Self {
+        MultiTrie {
+            inner: Trie::default(),
+        }
+    }
+
+    pub fn get(&self, key: &Path) -> &[T] {
+        self.inner.get(key).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    /// Calls `f` on the values associated with the given key, if any. The key
+    /// is removed if no value remains afterwards.
+    pub fn update<F: FnOnce(&mut Vec<T>)>(&mut self, key: &Path, f: F) {
+        if let Some(values) = self.inner.get_mut(key) {
+            f(values);
+
+            if values.is_empty() {
+                self.inner.remove(key);
+            }
+        }
+    }
+
+    pub fn get_ancestor_record(&self, key: &Path) -> Option<(&String, &Path, &[T])> {
+        self.inner.get_ancestor_record(key).map(|(k, p, v)| (k, p, v.as_slice()))
+    }
+
+    pub fn get_ancestor_path(&self, key: &Path) -> Option<&Path> {
+        self.inner.get_ancestor_path(key)
+    }
+
+    pub fn get_ancestor_values(&self, key: &Path) -> &[T] {
+        self.inner.get_ancestor_value(key).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    /// Appends a value to the list associated with the given key.
+    pub fn insert(&mut self, key: Path, value: T) {
+        match self.inner.get_mut(&key) {
+            Some(values) => values.push(value),
+            None => self.inner.insert(key, vec![value]),
+        }
+    }
+
+    /// Removes all the values associated with the given key.
+    pub fn remove(&mut self, key: &Path) {
+        self.inner.remove(key);
+    }
+
+    /// Removes the values associated with the given key that don't match the
+    /// predicate. The key itself is removed if no value remains.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, key: &Path, f: F) {
+        self.update(key, |values| values.retain(f));
+    }
+
+    /// Returns the number of keys in the trie.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn iter(&self) -> TrieIter<'_, Vec<T>> {
+        self.inner.iter()
+    }
+}
+
+/// A stack of tries that are queried in priority order, the first layer
+/// having the highest priority (for example user overrides, then project
+/// settings, then defaults).
+#[derive(Debug)]
+pub struct LayeredTrie<'a, T> {
+    layers: Vec<&'a Trie<T>>,
+}
+
+impl<T> Default for LayeredTrie<'_, T> {
+    fn default() -> Self {
+        LayeredTrie {
+            layers: Vec::new(),
+        }
+    }
+}
+
+impl<T> Clone for LayeredTrie<'_, T> {
+    fn clone(&self) -> Self {
+        LayeredTrie {
+            layers: self.layers.clone(),
+        }
+    }
+}
+
+impl<'a, T> LayeredTrie<'a, T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer with a lower priority than all the existing ones.
+    pub fn with_layer(mut self, layer: &'a Trie<T>) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    pub fn push_layer(&mut self, layer: &'a Trie<T>) -> &mut Self {
+        self.layers.push(layer);
+        self
+    }
+
+    pub fn layers(&self) -> &[&'a Trie<T>] {
+        &self.layers
+    }
+
+    /// Returns the value from the first layer that contains the given key.
+    pub fn get(&self, key: &Path) -> Option<&'a T> {
+        self.layers.iter().find_map(|layer| layer.get(key))
+    }
+
+    /// Returns the values from all the layers that contain the given key, in
+    /// priority order.
+    pub fn get_all(&self, key: &Path) -> Vec<&'a T> {
+        self.layers.iter().filter_map(|layer| layer.get(key)).collect()
+    }
+
+    /// Returns the index of the first layer that has an ancestor for the given
+    /// key, along with the ancestor path and value. A higher priority layer
+    /// wins even if a lower priority one has a closer ancestor.
+    pub fn get_ancestor_record(&self, key: &Path) -> Option<(usize, &'a Path, &'a T)> {
+        self.layers.iter().enumerate().find_map(|(index, layer)| {
+            layer.get_ancestor_record(key).map(|(_, p, v)| (index, p, v))
+        })
+    }
+
+    pub fn get_ancestor_value(&self, key: &Path) -> Option<&'a T> {
+        self.layers.iter().find_map(|layer| layer.get_ancestor_value(key))
+    }
+
+    /// Returns the closest ancestor value from each layer that has one, in
+    /// priority order.
+    pub fn get_ancestor_values(&self, key: &Path) -> Vec<&'a T> {
+        self.layers.iter().filter_map(|layer| layer.get_ancestor_value(key)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_trie_insert() {
+        let mut trie = MultiTrie::new();
+        trie.insert(Path::from("/path/to/item"), 1);
+        trie.insert(Path::from("/path/to/item/"), 2);
+        trie.insert(Path::from("/path/to/other"), 3);
+
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.get(&Path::from("/path/to/item")), &[1, 2]);
+        assert_eq!(trie.get(&Path::from("/path/to/other")), &[3]);
+        assert_eq!(trie.get(&Path::from("/path/to/nope")), &[] as &[i32]);
+    }
+
+    #[test]
+    fn test_multi_trie_get_ancestor_values() {
+        let mut trie = MultiTrie::new();
+        trie.insert(Path::from("/path"), 1);
+        trie.insert(Path::from("/path/to"), 2);
+        trie.insert(Path::from("/path/to"), 3);
+
+        assert_eq!(trie.get_ancestor_values(&Path::from("/path/to/item")), &[2, 3]);
+        assert_eq!(trie.get_ancestor_values(&Path::from("/path/item")), &[1]);
+        assert_eq!(trie.get_ancestor_path(&Path::from("/path/to/item")), Some(&Path::from("/path/to/")));
+    }
+
+    #[test]
+    fn test_multi_trie_update() {
+        // Values don't need to implement Default
+        struct Value(i32);
+
+        let mut trie = MultiTrie::default();
+        trie.insert(Path::from("/path/to/item"), Value(1));
+        trie.insert(Path::from("/path/to/item"), Value(2));
+
+        trie.update(&Path::from("/path/to/item"), |values| values.push(Value(3)));
+        assert_eq!(trie.get(&Path::from("/path/to/item")).iter().map(|value| value.0).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        trie.update(&Path::from("/path/to/item"), |values| values.clear());
+        assert!(trie.is_empty());
+    }
+
+    #[test]
+    fn test_multi_trie_retain() {
+        let mut trie = MultiTrie::new();
+        trie.insert(Path::from("/path"), 1);
+        trie.insert(Path::from("/path"), 2);
+
+        trie.retain(&Path::from("/path"), |v| *v != 1);
+        assert_eq!(trie.get(&Path::from("/path")), &[2]);
+
+        trie.retain(&Path::from("/path"), |v| *v != 2);
+        assert!(trie.is_empty());
+    }
+
+    fn make_layers() -> (Trie<&'static str>, Trie<&'static str>, Trie<&'static str>) {
+        let mut user = Trie::default();
+        user.insert(Path::from("/repo/a"), "user:a");
+
+        let mut project = Trie::default();
+        project.insert(Path::from("/repo"), "project:repo");
+        project.insert(Path::from("/repo/a/b"), "project:b");
+
+        let mut defaults = Trie::default();
+        defaults.insert(Path::root(), "defaults:root");
+        defaults.insert(Path::from("/repo/a"), "defaults:a");
+
+        (user, project, defaults)
+    }
+
+    #[test]
+    fn test_layered_trie_get() {
+        let (user, project, defaults) = make_layers();
+
+        let layers = LayeredTrie::new()
+            .with_layer(&user)
+            .with_layer(&project)
+            .with_layer(&defaults);
+
+        assert_eq!(layers.get(&Path::from("/repo/a")), Some(&"user:a"));
+        assert_eq!(layers.get(&Path::from("/repo")), Some(&"project:repo"));
+        assert_eq!(layers.get(&Path::from("/nope")), None);
+        assert_eq!(layers.get_all(&Path::from("/repo/a")), vec![&"user:a", &"defaults:a"]);
+    }
+
+    #[test]
+    fn test_layered_trie_get_ancestor_value() {
+        let (user, project, defaults) = make_layers();
+
+        let layers = LayeredTrie::new()
+            .with_layer(&user)
+            .with_layer(&project)
+            .with_layer(&defaults);
+
+        assert_eq!(layers.get_ancestor_value(&Path::from("/repo/a/b/c")), Some(&"user:a"));
+        assert_eq!(layers.get_ancestor_value(&Path::from("/repo/c")), Some(&"project:repo"));
+        assert_eq!(layers.get_ancestor_value(&Path::from("/other")), Some(&"defaults:root"));
+
+        assert_eq!(layers.get_ancestor_values(&Path::from("/repo/a/b/c")), vec![&"user:a", &"project:b", &"defaults:a"]);
+
+        assert_eq!(layers.get_ancestor_record(&Path::from("/repo/c")), Some((1, &Path::from("/repo/"), &"project:repo")));
+    }
+}
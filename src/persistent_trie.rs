@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::Path;
+
+struct Entry<T> {
+    key: String,
+    path: Path,
+    value: T,
+}
+
+struct Node<T> {
+    entry: Option<Arc<Entry<T>>>,
+    children: BTreeMap<String, Arc<Node<T>>>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            entry: None,
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Clone for Node<T> {
+    fn clone(&self) -> Self {
+        Node {
+            entry: self.entry.clone(),
+            children: self.children.clone(),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.entry.is_none() && self.children.is_empty()
+    }
+
+    fn with_entry(node: Option<&Node<T>>, segments: &[&str], entry: Arc<Entry<T>>) -> Node<T> {
+        let mut copy = node.cloned().unwrap_or_default();
+
+        if let Some((segment, rest)) = segments.split_first() {
+            let child = copy.children.get(*segment).map(|c| c.as_ref());
+            let child = Node::with_entry(child, rest, entry);
+
+            copy.children.insert(segment.to_string(), Arc::new(child));
+        } else {
+            copy.entry = Some(entry);
+        }
+
+        copy
+    }
+
+    fn without_entry(&self, segments: &[&str]) -> Node<T> {
+        let mut copy = self.clone();
+
+        if let Some((segment, rest)) = segments.split_first() {
+            if let Some(child) = copy.children.get(*segment) {
+                let child = child.without_entry(rest);
+
+                if child.is_empty() {
+                    copy.children.remove(*segment);
+                } else {
+                    copy.children.insert(segment.to_string(), Arc::new(child));
+                }
+            }
+        } else {
+            copy.entry = None;
+        }
+
+        copy
+    }
+}
+
+/// A trie keyed by paths whose `insert` and `remove` operations return a new
+/// version of the trie instead of mutating it. Both versions share all the
+/// nodes that weren't touched, so cloning a `PersistentTrie` is O(1).
+pub struct PersistentTrie<T> {
+    root: Arc<Node<T>>,
+    len: usize,
+}
+
+impl<T> Default for PersistentTrie<T> {
+    fn default() -> Self {
+        PersistentTrie {
+            root: Arc::new(Node::default()),
+            len: 0,
+        }
+    }
+}
+
+impl<T> Clone for PersistentTrie<T> {
+    fn clone(&self) -> Self {
+        PersistentTrie {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for PersistentTrie<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter().map(|(k, _, v)| (k, v))).finish()
+    }
+}
+
+impl<T> PersistentTrie<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(&self, key: &Path) -> String {
+        let mut p = key.to_string();
+
+        if !p.ends_with('/') {
+            p.push('/');
+        }
+
+        p
+    }
+
+    fn segments(key: &str) -> Vec<&str> {
+        key[..key.len() - 1].split('/').collect()
+    }
+
+    fn find_entry(&self, key: &Path) -> Option<&Entry<T>> {
+        let k = self.key(key);
+        let mut node = self.root.as_ref();
+
+        for segment in Self::segments(&k) {
+            node = node.children.get(segment)?;
+        }
+
+        node.entry.as_deref()
+    }
+
+    fn find_ancestor_entry(&self, key: &Path) -> Option<&Entry<T>> {
+        let k = self.key(key);
+        let mut node = self.root.as_ref();
+        let mut best = None;
+
+        for segment in Self::segments(&k) {
+            match node.children.get(segment) {
+                Some(child) => node = child,
+                None => break,
+            }
+
+            if let Some(entry) = &node.entry {
+                best = Some(entry.as_ref());
+            }
+        }
+
+        best
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns true if both tries are the exact same version (ie one is a
+    /// clone of the other, with no modification made since then).
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
+    pub fn get(&self, key: &Path) -> Option<&T> {
+        self.find_entry(key).map(|e| &e.value)
+    }
+
+    pub fn get_ancestor_record(&self, key: &Path) -> Option<(&String, &Path, &T)> {
+        self.find_ancestor_entry(key).map(|e| (&e.key, &e.path, &e.value))
+    }
+
+    pub fn get_ancestor_key(&self, key: &Path) -> Option<&String> {
+        self.find_ancestor_entry(key).map(|e| &e.key)
+    }
+
+    pub fn get_ancestor_path(&self, key: &Path) -> Option<&Path> {
+        self.find_ancestor_entry(key).map(|e| &e.path)
+    }
+
+    pub fn get_ancestor_value(&self, key: &Path) -> Option<&T> {
+        self.find_ancestor_entry(key).map(|e| &e.value)
+    }
+
+    pub fn insert(&self, key: Path, value: T) -> Self {
+        let k = self.key(&key);
+        let p = Path::from(k.clone());
+
+        let len = match self.find_entry(&key) {
+            Some(_) => self.len,
+            None => self.len + 1,
+        };
+
+        let segments = Self::segments(&k);
+        let entry = Arc::new(Entry {key: k.clone(), path: p, value});
+
+        PersistentTrie {
+            root: Arc::new(Node::with_entry(Some(&self.root), &segments, entry)),
+            len,
+        }
+    }
+
+    pub fn remove(&self, key: &Path) -> Self {
+        if self.find_entry(key).is_none() {
+            return self.clone();
+        }
+
+        let k = self.key(key);
+        let segments = Self::segments(&k);
+
+        PersistentTrie {
+            root: Arc::new(self.root.without_entry(&segments)),
+            len: self.len - 1,
+        }
+    }
+
+    /// Iterates over all the entries in the trie, sorted by path segments.
+    pub fn iter(&self) -> PersistentTrieIter<'_, T> {
+        PersistentTrieIter {
+            stack: vec![self.root.as_ref()],
+        }
+    }
+}
+
+pub struct PersistentTrieIter<'a, T> {
+    stack: Vec<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for PersistentTrieIter<'a, T> {
+    type Item = (&'a String, &'a Path, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            self.stack.extend(node.children.values().rev().map(|c| c.as_ref()));
+
+            if let Some(entry) = &node.entry {
+                return Some((&entry.key, &entry.path, &entry.value));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persistent_trie_insert() {
+        let trie = PersistentTrie::new();
+        let path = Path::from("/path/to/item/");
+
+        let next = trie.insert(path.clone(), "item");
+
+        assert_eq!(trie.get(&path), None);
+        assert_eq!(next.get(&path), Some(&"item"));
+        assert_eq!(next.len(), 1);
+    }
+
+    #[test]
+    fn test_persistent_trie_insert_replace() {
+        let path = Path::from("/path/to/item");
+
+        let v1 = PersistentTrie::new().insert(path.clone(), 1);
+        let v2 = v1.insert(path.clone(), 2);
+
+        assert_eq!(v1.get(&path), Some(&1));
+        assert_eq!(v2.get(&path), Some(&2));
+        assert_eq!(v2.len(), 1);
+    }
+
+    #[test]
+    fn test_persistent_trie_remove() {
+        let path = Path::from("/path/to/item/");
+
+        let v1 = PersistentTrie::new().insert(path.clone(), "item");
+        let v2 = v1.remove(&path);
+
+        assert_eq!(v1.get(&path), Some(&"item"));
+        assert_eq!(v2.get(&path), None);
+        assert!(v2.is_empty());
+    }
+
+    #[test]
+    fn test_persistent_trie_remove_missing() {
+        let v1 = PersistentTrie::new().insert(Path::from("/foo"), 1);
+        let v2 = v1.remove(&Path::from("/bar"));
+
+        assert!(v1.ptr_eq(&v2));
+    }
+
+    #[test]
+    fn test_persistent_trie_structural_sharing() {
+        let v1 = PersistentTrie::new()
+            .insert(Path::from("/a/b"), 1)
+            .insert(Path::from("/c/d"), 2);
+
+        let v2 = v1.insert(Path::from("/a/e"), 3);
+
+        let c1 = &v1.root.children[""].children["c"];
+        let c2 = &v2.root.children[""].children["c"];
+
+        assert!(Arc::ptr_eq(c1, c2));
+    }
+
+    #[test]
+    fn test_persistent_trie_get_ancestor() {
+        let trie = PersistentTrie::new()
+            .insert(Path::from("/path/to/"), "parent")
+            .insert(Path::from("/path/to/item/"), "item");
+
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path/to/item/child")), Some(&"item"));
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path/to/item")), Some(&"item"));
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path/to/items")), Some(&"parent"));
+        assert_eq!(trie.get_ancestor_key(&Path::from("/path/to/other")).unwrap(), "/path/to/");
+        assert_eq!(trie.get_ancestor_path(&Path::from("/path/to/other")), Some(&Path::from("/path/to/")));
+        assert_eq!(trie.get_ancestor_value(&Path::from("/path")), None);
+    }
+
+    #[test]
+    fn test_persistent_trie_iter() {
+        let trie = PersistentTrie::new()
+            .insert(Path::from("/b"), 2)
+            .insert(Path::from("/a/c"), 3)
+            .insert(Path::from("/a"), 1);
+
+        let keys = trie.iter()
+            .map(|(k, _, v)| (k.as_str(), *v))
+            .collect::<Vec<_>>();
+
+        assert_eq!(keys, vec![("/a/", 1), ("/a/c/", 3), ("/b/", 2)]);
+    }
+}
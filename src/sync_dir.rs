@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fs, io};
+
+use crate::changeset::{Changeset, ChangesetErr, ChangesetMode, ChangesetReport};
+use crate::{OkMissing, Path};
+
+/// Desired state of an entry inside a directory synchronized through
+/// `Path::fs_sync_dir`.
+#[derive(Debug, Clone)]
+pub enum SyncEntry {
+    File {
+        data: Vec<u8>,
+        permissions: fs::Permissions,
+    },
+
+    Symlink {
+        target: Path,
+    },
+
+    Directory,
+}
+
+impl SyncEntry {
+    pub fn file<T: Into<Vec<u8>>>(data: T, permissions: fs::Permissions) -> Self {
+        SyncEntry::File {data: data.into(), permissions}
+    }
+
+    pub fn symlink(target: Path) -> Self {
+        SyncEntry::Symlink {target}
+    }
+}
+
+fn collect_removals(dir: &Path, rel: &Path, entries: &BTreeMap<Path, SyncEntry>, dirs: &BTreeSet<Path>, changeset: &mut Changeset) -> io::Result<()> {
+    for (abs_path, file_type) in dir.fs_read_dir_entries()? {
+        let rel_path = rel.with_entry_name(abs_path.basename().unwrap());
+
+        if file_type.is_dir() {
+            if dirs.contains(&rel_path) {
+                collect_removals(&abs_path, &rel_path, entries, dirs, changeset)?;
+            } else {
+                changeset.remove(abs_path);
+            }
+
+            continue;
+        }
+
+        let keep = match entries.get(&rel_path) {
+            Some(SyncEntry::File {..}) => file_type.is_file(),
+            Some(SyncEntry::Symlink {..}) => file_type.is_symlink(),
+            _ => false,
+        };
+
+        if !keep {
+            changeset.remove(abs_path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the changeset turning the content of `root` into the given entries,
+/// whose keys are paths relative to `root`.
+pub(crate) fn sync_dir_changeset(root: &Path, entries: &BTreeMap<Path, SyncEntry>) -> io::Result<Changeset> {
+    let mut dirs = BTreeSet::new();
+
+    for (rel_path, entry) in entries {
+        if !rel_path.is_forward() || rel_path.as_str().is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Expected a forward relative path, got {}", rel_path)));
+        }
+
+        if let SyncEntry::Directory = entry {
+            dirs.insert(rel_path.clone());
+        }
+
+        let mut parent = rel_path.dirname();
+
+        while let Some(dir) = parent {
+            parent = dir.dirname();
+            dirs.insert(dir);
+        }
+    }
+
+    let mut changeset = Changeset::new();
+
+    let root_metadata = fs::symlink_metadata(root.to_path_buf())
+        .ok_missing()?;
+
+    match root_metadata {
+        Some(metadata) if metadata.is_dir() => {
+            collect_removals(root, &Path::new(), entries, &dirs, &mut changeset)?;
+        },
+
+        Some(_) => {
+            changeset.remove(root.clone());
+            changeset.create_dir(root.clone());
+        },
+
+        None => {
+            changeset.create_dir(root.clone());
+        },
+    }
+
+    for dir in &dirs {
+        changeset.create_dir(root.with_join(dir));
+    }
+
+    for (rel_path, entry) in entries {
+        let abs_path = root.with_join(rel_path);
+
+        match entry {
+            SyncEntry::File {data, permissions} => {
+                changeset.write(abs_path, data.clone(), permissions.clone());
+            },
+
+            SyncEntry::Symlink {target} => {
+                changeset.symlink(abs_path, target.clone());
+            },
+
+            SyncEntry::Directory => {},
+        }
+    }
+
+    Ok(changeset)
+}
+
+pub(crate) fn sync_dir(root: &Path, entries: &BTreeMap<Path, SyncEntry>, mode: ChangesetMode) -> Result<ChangesetReport, ChangesetErr> {
+    sync_dir_changeset(root, entries)?.run(mode)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::changeset::ChangeKind;
+
+    fn make_entries() -> BTreeMap<Path, SyncEntry> {
+        let mut entries = BTreeMap::new();
+        entries.insert(Path::from("foo"), SyncEntry::symlink(Path::from("../lib/foo.js")));
+        entries.insert(Path::from("bar"), SyncEntry::file("#!/bin/sh", fs::Permissions::from_mode(0o755)));
+        entries.insert(Path::from("nested/baz"), SyncEntry::file("baz", fs::Permissions::from_mode(0o644)));
+        entries.insert(Path::from("empty"), SyncEntry::Directory);
+        entries
+    }
+
+    #[test]
+    fn test_sync_dir_create() {
+        let dir = Path::temp_dir().unwrap();
+        let bin = dir.with_join_str("bin");
+
+        bin.fs_sync_dir(&make_entries(), ChangesetMode::Apply).unwrap();
+
+        assert_eq!(fs::read_link(bin.with_join_str("foo").to_path_buf()).unwrap().to_str(), Some("../lib/foo.js"));
+        assert_eq!(bin.with_join_str("bar").fs_read_text().unwrap(), "#!/bin/sh");
+        assert_eq!(bin.with_join_str("nested/baz").fs_read_text().unwrap(), "baz");
+        assert!(bin.with_join_str("empty").fs_is_dir());
+
+        let report = bin.fs_sync_dir(&make_entries(), ChangesetMode::Check).unwrap();
+        assert!(report.is_empty());
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_sync_dir_update() {
+        let dir = Path::temp_dir().unwrap();
+        let bin = dir.with_join_str("bin");
+
+        bin.with_join_str("stale").fs_create_dir_all().unwrap();
+        bin.with_join_str("stale/file").fs_write("stale").unwrap();
+        bin.with_join_str("old").fs_write("old").unwrap();
+        bin.with_join_str("foo").fs_write("not a symlink").unwrap();
+        bin.with_join_str("nested").fs_write("not a directory").unwrap();
+
+        let report = bin.fs_sync_dir(&make_entries(), ChangesetMode::Check).unwrap_err();
+        assert!(matches!(report, ChangesetErr::Immutable(_)));
+        assert!(bin.with_join_str("old").fs_exists());
+
+        let report = bin.fs_sync_dir(&make_entries(), ChangesetMode::Apply).unwrap();
+
+        let removed = report.iter()
+            .filter(|change| change.kind == ChangeKind::Remove)
+            .map(|change| change.path.clone())
+            .collect::<BTreeSet<_>>();
+
+        assert_eq!(removed, BTreeSet::from([
+            bin.with_join_str("foo"),
+            bin.with_join_str("nested"),
+            bin.with_join_str("old"),
+            bin.with_join_str("stale"),
+        ]));
+
+        let mut names = bin.fs_read_dir_names().unwrap();
+        names.sort();
+
+        assert_eq!(names, vec!["bar", "empty", "foo", "nested"]);
+        assert!(bin.fs_sync_dir(&make_entries(), ChangesetMode::Check).unwrap().is_empty());
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_sync_dir_invalid_path() {
+        let dir = Path::temp_dir().unwrap();
+
+        let mut entries = BTreeMap::new();
+        entries.insert(Path::from("../escape"), SyncEntry::Directory);
+
+        assert!(matches!(dir.fs_sync_dir(&entries, ChangesetMode::Apply), Err(ChangesetErr::Io(_))));
+
+        dir.fs_rm().unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::{fs, io};
+
+use crate::{temp_nonce, Path, ToArcaPath, TEMP_ATTEMPTS};
+
+/// Calls `create` with paths derived from the pattern (where `<>` is replaced
+/// by a unique nonce) until it doesn't fail with `AlreadyExists`.
+pub(crate) fn create_unique<T, F>(parent: &Path, pattern: &str, mut create: F) -> io::Result<(Path, T)>
+where
+    F: FnMut(&Path) -> io::Result<T>,
+{
+    let (before, after) = pattern.split_once("<>")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Expected the pattern to contain a <> placeholder, got {}", pattern)))?;
+
+    for _ in 0..TEMP_ATTEMPTS {
+        let path = parent.with_join_str(format!("{}{}{}", before, temp_nonce(), after));
+
+        match create(&path) {
+            Ok(value) => return Ok((path, value)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "Failed to find an available temporary name"))
+}
+
+/// Temporary directory removed (along with its content) when dropped.
+#[derive(Debug)]
+pub struct TempDir {
+    path: Path,
+    keep: bool,
+}
+
+impl TempDir {
+    pub fn new() -> io::Result<Self> {
+        Self::new_in(&std::env::temp_dir().to_arca())
+    }
+
+    pub fn new_in(parent: &Path) -> io::Result<Self> {
+        Self::with_pattern_in(parent, "temp-<>")
+    }
+
+    /// Creates the directory inside `parent` (created if needed), naming it
+    /// after the pattern with its `<>` placeholder replaced by a unique nonce.
+    pub fn with_pattern_in(parent: &Path, pattern: &str) -> io::Result<Self> {
+        parent.fs_create_dir_all()?;
+
+        let (path, _) = create_unique(parent, pattern, |path| {
+            fs::create_dir(path.to_path_buf())
+        })?;
+
+        Ok(TempDir {path, keep: false})
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Disables the cleanup and returns the path of the directory.
+    pub fn keep(mut self) -> Path {
+        self.keep = true;
+        self.path.clone()
+    }
+
+    /// Removes the directory, reporting the errors that dropping the guard
+    /// would ignore.
+    pub fn close(mut self) -> io::Result<()> {
+        self.keep = true;
+        fs::remove_dir_all(self.path.to_path_buf())
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(self.path.to_path_buf());
+        }
+    }
+}
+
+// Kept apart from the file handle so that persisting the file can move the
+// handle out without going through the cleanup
+#[derive(Debug)]
+struct TempPath {
+    path: Path,
+    keep: bool,
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_file(self.path.to_path_buf());
+        }
+    }
+}
+
+/// Temporary file removed when dropped, unless persisted somewhere else.
+#[derive(Debug)]
+pub struct NamedTempFile {
+    path: TempPath,
+    file: fs::File,
+}
+
+impl NamedTempFile {
+    pub fn new() -> io::Result<Self> {
+        Self::new_in(&std::env::temp_dir().to_arca())
+    }
+
+    pub fn new_in(parent: &Path) -> io::Result<Self> {
+        Self::with_pattern_in(parent, ".tmp-<>")
+    }
+
+    /// Creates the file inside `parent` (which must exist), exclusively and
+    /// readable only by the current user.
+    pub fn with_pattern_in(parent: &Path, pattern: &str) -> io::Result<Self> {
+        let (path, file) = create_unique(parent, pattern, |path| {
+            let mut options = fs::OpenOptions::new();
+            options.read(true).write(true).create_new(true);
+
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            options.open(path.to_path_buf())
+        })?;
+
+        Ok(NamedTempFile {path: TempPath {path, keep: false}, file})
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path.path
+    }
+
+    pub fn as_file(&self) -> &fs::File {
+        &self.file
+    }
+
+    pub fn as_file_mut(&mut self) -> &mut fs::File {
+        &mut self.file
+    }
+
+    /// Atomically moves the file to `destination`, which must be located on
+    /// the same filesystem. On failure, the temporary file is returned along
+    /// with the error so that it can be persisted some other way.
+    pub fn persist(self, destination: &Path) -> Result<fs::File, PersistError> {
+        if let Err(error) = fs::rename(self.path().to_path_buf(), destination.to_path_buf()) {
+            return Err(PersistError {file: self, error});
+        }
+
+        let NamedTempFile {mut path, file} = self;
+        path.keep = true;
+
+        Ok(file)
+    }
+
+    /// Disables the cleanup and returns the file along with its path.
+    pub fn keep(self) -> io::Result<(fs::File, Path)> {
+        let NamedTempFile {mut path, file} = self;
+        path.keep = true;
+
+        Ok((file, path.path.clone()))
+    }
+}
+
+/// Returned by `NamedTempFile::persist`; the temporary file is still there.
+#[derive(Debug)]
+pub struct PersistError {
+    pub file: NamedTempFile,
+    pub error: io::Error,
+}
+
+impl PersistError {
+    pub fn into_io_error(self) -> io::Error {
+        self.error
+    }
+}
+
+impl Display for PersistError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file.path(), self.error)
+    }
+}
+
+impl std::error::Error for PersistError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<PersistError> for io::Error {
+    fn from(err: PersistError) -> Self {
+        err.error
+    }
+}
+
+impl Read for NamedTempFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for NamedTempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for NamedTempFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_dir_drop() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().clone();
+
+        dir.with_join_str("nested/file").fs_create_parent().unwrap();
+        dir.with_join_str("nested/file").fs_write("hello").unwrap();
+
+        assert!(path.fs_is_dir());
+        drop(dir);
+        assert!(!path.fs_exists());
+    }
+
+    #[test]
+    fn test_temp_dir_keep() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.keep();
+
+        assert!(path.fs_is_dir());
+        path.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_temp_dir_pattern_in() {
+        let parent = TempDir::new().unwrap();
+
+        let first = TempDir::with_pattern_in(&parent.with_join_str("sub"), "cache-<>.d").unwrap();
+        let second = TempDir::with_pattern_in(&parent.with_join_str("sub"), "cache-<>.d").unwrap();
+
+        assert_ne!(first.path(), second.path());
+        assert_eq!(first.dirname(), Some(parent.with_join_str("sub")));
+        assert!(first.basename().unwrap().starts_with("cache-"));
+        assert!(first.basename().unwrap().ends_with(".d"));
+
+        first.close().unwrap();
+        assert!(second.fs_is_dir());
+    }
+
+    #[test]
+    fn test_temp_dir_invalid_pattern() {
+        let err = TempDir::with_pattern_in(&std::env::temp_dir().to_arca(), "no-placeholder").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_temp_file_drop() {
+        let mut file = Path::temp_file().unwrap();
+        let path = file.path().clone();
+
+        file.write_all(b"hello").unwrap();
+        assert_eq!(path.fs_read_text().unwrap(), "hello");
+
+        drop(file);
+        assert!(!path.fs_exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_temp_file_permissions() {
+        use std::os::unix::fs::MetadataExt;
+
+        let file = Path::temp_file().unwrap();
+        assert_eq!(file.path().fs_metadata().unwrap().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_temp_file_persist() {
+        let dir = TempDir::new().unwrap();
+        let destination = dir.with_join_str("generated.txt");
+
+        let mut file = NamedTempFile::new_in(&dir).unwrap();
+        let path = file.path().clone();
+
+        file.write_all(b"generated").unwrap();
+        file.persist(&destination).unwrap();
+
+        assert!(!path.fs_exists());
+        assert_eq!(destination.fs_read_text().unwrap(), "generated");
+        assert_eq!(dir.fs_read_dir_names().unwrap(), vec!["generated.txt"]);
+    }
+
+    #[test]
+    fn test_temp_file_persist_error() {
+        let dir = TempDir::new().unwrap();
+        let destination = dir.with_join_str("missing/generated.txt");
+
+        let file = NamedTempFile::new_in(&dir).unwrap();
+        let path = file.path().clone();
+
+        let err = file.persist(&destination).unwrap_err();
+        assert_eq!(err.error.kind(), io::ErrorKind::NotFound);
+        assert!(path.fs_exists());
+
+        // The file can still be persisted elsewhere
+        let destination = dir.with_join_str("generated.txt");
+        err.file.persist(&destination).unwrap();
+
+        assert_eq!(dir.fs_read_dir_names().unwrap(), vec!["generated.txt"]);
+    }
+}
@@ -0,0 +1,801 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::{fs, io};
+
+use crate::Path;
+
+/// Maximum number of symlinks followed while resolving a path in `MemoryFs`,
+/// same as Linux.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Kind of a filesystem entry. Anything that isn't a directory or a symlink
+/// (sockets, fifos, ...) is reported as a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsFileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// Subset of the metadata that every `Fs` implementation can provide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsMetadata {
+    pub file_type: FsFileType,
+    pub len: u64,
+
+    /// Unix permission bits. Only the write bits are meaningful on other
+    /// platforms, where they map to the readonly flag.
+    pub mode: u32,
+
+    pub modified: Option<SystemTime>,
+}
+
+impl FsMetadata {
+    pub fn is_file(&self) -> bool {
+        self.file_type == FsFileType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FsFileType::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FsFileType::Symlink
+    }
+}
+
+/// Filesystem operations used by `FsPath`. Implement it to run the same code
+/// against the real disk, in-memory fixtures, archives, ...
+pub trait Fs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Creates the file if needed, and replaces its content.
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Follows symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata>;
+
+    /// Doesn't follow symlinks.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata>;
+
+    /// Returns the entries of the directory in no particular order, joined to
+    /// the path, along with their type (symlinks aren't followed).
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(Path, FsFileType)>>;
+
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Only removes empty directories.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates a symlink at `path` pointing to `target`.
+    fn symlink(&self, path: &Path, target: &Path) -> io::Result<()>;
+
+    fn read_link(&self, path: &Path) -> io::Result<Path>;
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()>;
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        match self.metadata(path) {
+            Ok(metadata) if metadata.is_dir() => return Ok(()),
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and isn't a directory", path))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err),
+        }
+
+        if let Some(parent) = path.dirname() {
+            self.create_dir_all(&parent)?;
+        }
+
+        match self.create_dir(path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && self.metadata(path).is_ok_and(|metadata| metadata.is_dir()) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        for (entry, file_type) in self.read_dir(path)? {
+            match file_type {
+                FsFileType::Dir => self.remove_dir_all(&entry)?,
+                _ => self.remove_file(&entry)?,
+            }
+        }
+
+        self.remove_dir(path)
+    }
+}
+
+/// `Fs` implementation backed by `std::fs`, behaving like the `fs_*` methods
+/// of `Path`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl RealFs {
+    fn convert_metadata(metadata: fs::Metadata) -> FsMetadata {
+        let file_type = match metadata.file_type() {
+            file_type if file_type.is_dir() => FsFileType::Dir,
+            file_type if file_type.is_symlink() => FsFileType::Symlink,
+            _ => FsFileType::File,
+        };
+
+        #[cfg(unix)]
+        let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o7777;
+
+        #[cfg(not(unix))]
+        let mode = match metadata.permissions().readonly() {
+            true => 0o444,
+            false => 0o644,
+        };
+
+        FsMetadata {
+            file_type,
+            len: metadata.len(),
+            mode,
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+impl Fs for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        path.fs_read()
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        path.fs_write(data)?;
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        Ok(Self::convert_metadata(path.fs_metadata()?))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        Ok(Self::convert_metadata(path.fs_symlink_metadata()?))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(Path, FsFileType)>> {
+        Ok(path.fs_read_dir_entries()?
+            .into_iter()
+            .map(|(entry, file_type)| {
+                let file_type = match file_type {
+                    file_type if file_type.is_dir() => FsFileType::Dir,
+                    file_type if file_type.is_symlink() => FsFileType::Symlink,
+                    _ => FsFileType::File,
+                };
+
+                (entry, file_type)
+            })
+            .collect())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        path.fs_create_dir()?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        from.fs_rename(to)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        path.fs_rm_file()?;
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path.to_path_buf())
+    }
+
+    fn symlink(&self, path: &Path, target: &Path) -> io::Result<()> {
+        path.fs_symlink(target)?;
+        Ok(())
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<Path> {
+        path.fs_read_link()
+    }
+
+    #[cfg(unix)]
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        path.fs_set_permissions(fs::Permissions::from_mode(mode))?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut permissions = path.fs_metadata()?.permissions();
+        permissions.set_readonly(mode & 0o222 == 0);
+
+        path.fs_set_permissions(permissions)?;
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        path.fs_create_dir_all()?;
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path.to_path_buf())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File {data: Vec<u8>, mode: u32, modified: SystemTime},
+    Dir {mode: u32, modified: SystemTime},
+    Symlink {target: Path},
+}
+
+impl Node {
+    fn metadata(&self) -> FsMetadata {
+        match self {
+            Node::File {data, mode, modified} => FsMetadata {
+                file_type: FsFileType::File,
+                len: data.len() as u64,
+                mode: *mode,
+                modified: Some(*modified),
+            },
+
+            Node::Dir {mode, modified} => FsMetadata {
+                file_type: FsFileType::Dir,
+                len: 0,
+                mode: *mode,
+                modified: Some(*modified),
+            },
+
+            Node::Symlink {target} => FsMetadata {
+                file_type: FsFileType::Symlink,
+                len: target.as_str().len() as u64,
+                mode: 0o777,
+                modified: None,
+            },
+        }
+    }
+
+    fn file_type(&self) -> FsFileType {
+        match self {
+            Node::File {..} => FsFileType::File,
+            Node::Dir {..} => FsFileType::Dir,
+            Node::Symlink {..} => FsFileType::Symlink,
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't exist", path))
+}
+
+/// Paths without parent (the root, or the empty path standing for the current
+/// directory) always exist as directories.
+fn is_implicit_dir(path: &Path) -> bool {
+    path.dirname().is_none() && (path.as_str().is_empty() || path.is_root() || path.as_str() == ".")
+}
+
+fn is_descendant(path: &Path, ancestor: &Path) -> bool {
+    path.as_str().strip_prefix(ancestor.as_str())
+        .is_some_and(|rest| rest.starts_with('/') || ancestor.as_str().ends_with('/'))
+}
+
+/// In-memory `Fs` implementation, meant for tests and fixtures. Symlinks are
+/// supported, relative targets being resolved from their parent directory.
+#[derive(Default)]
+pub struct MemoryFs {
+    nodes: Mutex<BTreeMap<Path, Node>>,
+}
+
+impl Debug for MemoryFs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryFs")
+            .field("entries", &self.nodes.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the path where the entry is stored, with all the symlinks of
+    /// its parents resolved (and of the entry itself when `follow` is set).
+    fn resolve(nodes: &BTreeMap<Path, Node>, path: &Path, follow: bool, hops: &mut usize) -> io::Result<Path> {
+        let (Some(parent), Some(name)) = (path.dirname(), path.basename()) else {
+            return Ok(path.clone());
+        };
+
+        let parent = Self::resolve(nodes, &parent, true, hops)?;
+        let resolved = parent.with_join_str(name);
+
+        match nodes.get(&resolved) {
+            Some(Node::Symlink {target}) if follow => {
+                *hops += 1;
+
+                if *hops > MAX_SYMLINK_HOPS {
+                    return Err(io::Error::other(format!("Too many levels of symbolic links while resolving {}", path)));
+                }
+
+                Self::resolve(nodes, &parent.with_join(target), true, hops)
+            },
+
+            _ => Ok(resolved),
+        }
+    }
+
+    /// Resolves the path, then returns its node, if any. Implicit directories
+    /// are returned as owned nodes.
+    fn lookup(nodes: &BTreeMap<Path, Node>, path: &Path, follow: bool) -> io::Result<(Path, Option<Node>)> {
+        let resolved = Self::resolve(nodes, path, follow, &mut 0)?;
+
+        let node = match is_implicit_dir(&resolved) {
+            true => Some(Node::Dir {mode: 0o755, modified: SystemTime::UNIX_EPOCH}),
+            false => nodes.get(&resolved).cloned(),
+        };
+
+        Ok((resolved, node))
+    }
+
+    fn check_parent(nodes: &BTreeMap<Path, Node>, path: &Path) -> io::Result<()> {
+        let Some(parent) = path.dirname() else {
+            return Ok(());
+        };
+
+        match Self::lookup(nodes, &parent, true)?.1 {
+            Some(Node::Dir {..}) => Ok(()),
+            Some(_) => Err(io::Error::new(io::ErrorKind::NotADirectory, format!("{} isn't a directory", parent))),
+            None => Err(not_found(&parent)),
+        }
+    }
+
+    fn has_children(nodes: &BTreeMap<Path, Node>, path: &Path) -> bool {
+        nodes.keys().any(|key| key.dirname().as_ref() == Some(path))
+    }
+}
+
+impl Fs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let nodes = self.nodes.lock().unwrap();
+
+        match Self::lookup(&nodes, path, true)?.1 {
+            Some(Node::File {data, ..}) => Ok(data),
+            Some(_) => Err(io::Error::new(io::ErrorKind::IsADirectory, format!("{} is a directory", path))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let (resolved, node) = Self::lookup(&nodes, path, true)?;
+
+        let mode = match node {
+            Some(Node::File {mode, ..}) => mode,
+            Some(_) => return Err(io::Error::new(io::ErrorKind::IsADirectory, format!("{} is a directory", path))),
+            None => {
+                Self::check_parent(&nodes, &resolved)?;
+                0o644
+            },
+        };
+
+        nodes.insert(resolved, Node::File {data: data.to_vec(), mode, modified: SystemTime::now()});
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        let nodes = self.nodes.lock().unwrap();
+
+        Self::lookup(&nodes, path, true)?.1
+            .map(|node| node.metadata())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FsMetadata> {
+        let nodes = self.nodes.lock().unwrap();
+
+        Self::lookup(&nodes, path, false)?.1
+            .map(|node| node.metadata())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(Path, FsFileType)>> {
+        let nodes = self.nodes.lock().unwrap();
+
+        let resolved = match Self::lookup(&nodes, path, true)? {
+            (resolved, Some(Node::Dir {..})) => resolved,
+            (_, Some(_)) => return Err(io::Error::new(io::ErrorKind::NotADirectory, format!("{} isn't a directory", path))),
+            (_, None) => return Err(not_found(path)),
+        };
+
+        Ok(nodes.iter()
+            .filter(|(key, _)| key.dirname().as_ref() == Some(&resolved))
+            .filter_map(|(key, node)| Some((path.with_join_str(key.basename()?), node.file_type())))
+            .collect())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let (resolved, node) = Self::lookup(&nodes, path, false)?;
+
+        if node.is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path)));
+        }
+
+        Self::check_parent(&nodes, &resolved)?;
+
+        nodes.insert(resolved, Node::Dir {mode: 0o755, modified: SystemTime::now()});
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+
+        let (from, from_node) = Self::lookup(&nodes, from, false)?;
+        let (to, to_node) = Self::lookup(&nodes, to, false)?;
+
+        let Some(from_node) = from_node else {
+            return Err(not_found(&from));
+        };
+
+        if is_implicit_dir(&from) || is_descendant(&to, &from) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Can't move {} into {}", from, to)));
+        }
+
+        if from == to {
+            return Ok(());
+        }
+
+        Self::check_parent(&nodes, &to)?;
+
+        match (&from_node, to_node) {
+            (Node::Dir {..}, Some(Node::Dir {..})) if Self::has_children(&nodes, &to) => {
+                return Err(io::Error::new(io::ErrorKind::DirectoryNotEmpty, format!("{} isn't empty", to)));
+            },
+
+            (Node::Dir {..}, Some(Node::File {..} | Node::Symlink {..})) => {
+                return Err(io::Error::new(io::ErrorKind::NotADirectory, format!("{} isn't a directory", to)));
+            },
+
+            (Node::File {..} | Node::Symlink {..}, Some(Node::Dir {..})) => {
+                return Err(io::Error::new(io::ErrorKind::IsADirectory, format!("{} is a directory", to)));
+            },
+
+            _ => {},
+        }
+
+        let moved = nodes.keys()
+            .filter(|key| is_descendant(key, &from))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for key in moved {
+            let node = nodes.remove(&key).unwrap();
+            nodes.insert(to.with_join(&key.relative_to(&from)), node);
+        }
+
+        nodes.remove(&from);
+        nodes.insert(to, from_node);
+
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+
+        match Self::lookup(&nodes, path, false)? {
+            (resolved, Some(Node::File {..} | Node::Symlink {..})) => {
+                nodes.remove(&resolved);
+                Ok(())
+            },
+
+            (_, Some(Node::Dir {..})) => Err(io::Error::new(io::ErrorKind::IsADirectory, format!("{} is a directory", path))),
+            (_, None) => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+
+        match Self::lookup(&nodes, path, false)? {
+            (resolved, Some(Node::Dir {..})) if is_implicit_dir(&resolved) => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Can't remove {}", path)))
+            },
+
+            (resolved, Some(Node::Dir {..})) => {
+                if Self::has_children(&nodes, &resolved) {
+                    return Err(io::Error::new(io::ErrorKind::DirectoryNotEmpty, format!("{} isn't empty", path)));
+                }
+
+                nodes.remove(&resolved);
+                Ok(())
+            },
+
+            (_, Some(_)) => Err(io::Error::new(io::ErrorKind::NotADirectory, format!("{} isn't a directory", path))),
+            (_, None) => Err(not_found(path)),
+        }
+    }
+
+    fn symlink(&self, path: &Path, target: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let (resolved, node) = Self::lookup(&nodes, path, false)?;
+
+        if node.is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path)));
+        }
+
+        Self::check_parent(&nodes, &resolved)?;
+
+        nodes.insert(resolved, Node::Symlink {target: target.clone()});
+        Ok(())
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<Path> {
+        let nodes = self.nodes.lock().unwrap();
+
+        match Self::lookup(&nodes, path, false)?.1 {
+            Some(Node::Symlink {target}) => Ok(target),
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't a symlink", path))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let resolved = Self::resolve(&nodes, path, true, &mut 0)?;
+
+        match nodes.get_mut(&resolved) {
+            Some(Node::File {mode: current, ..} | Node::Dir {mode: current, ..}) => {
+                *current = mode & 0o7777;
+                Ok(())
+            },
+
+            None if is_implicit_dir(&resolved) => Ok(()),
+            _ => Err(not_found(path)),
+        }
+    }
+}
+
+/// Path bound to a filesystem. Exposes the same `fs_*` methods as `Path`,
+/// except that they go through the `Fs` implementation instead of `std::fs`.
+pub struct FsPath<'a, F: Fs + ?Sized> {
+    path: Path,
+    fs: &'a F,
+}
+
+impl<F: Fs + ?Sized> Clone for FsPath<'_, F> {
+    fn clone(&self) -> Self {
+        FsPath {path: self.path.clone(), fs: self.fs}
+    }
+}
+
+impl<F: Fs + ?Sized> Debug for FsPath<'_, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FsPath").field(&self.path).finish()
+    }
+}
+
+impl<'a, F: Fs + ?Sized> FsPath<'a, F> {
+    pub fn new(fs: &'a F, path: &Path) -> Self {
+        FsPath {path: path.clone(), fs}
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn fs(&self) -> &'a F {
+        self.fs
+    }
+
+    pub fn dirname(&self) -> Option<FsPath<'a, F>> {
+        self.path.dirname().map(|path| FsPath {path, fs: self.fs})
+    }
+
+    pub fn with_join_str<T: AsRef<str>>(&self, other: T) -> FsPath<'a, F> {
+        FsPath {path: self.path.with_join_str(other.as_ref()), fs: self.fs}
+    }
+
+    pub fn fs_create_parent(&self) -> io::Result<&Self> {
+        if let Some(parent) = self.path.dirname() {
+            self.fs.create_dir_all(&parent)?;
+        }
+
+        Ok(self)
+    }
+
+    pub fn fs_create_dir_all(&self) -> io::Result<&Self> {
+        self.fs.create_dir_all(&self.path)?;
+        Ok(self)
+    }
+
+    pub fn fs_create_dir(&self) -> io::Result<&Self> {
+        self.fs.create_dir(&self.path)?;
+        Ok(self)
+    }
+
+    pub fn fs_set_permissions(&self, mode: u32) -> io::Result<&Self> {
+        self.fs.set_permissions(&self.path, mode)?;
+        Ok(self)
+    }
+
+    pub fn fs_metadata(&self) -> io::Result<FsMetadata> {
+        self.fs.metadata(&self.path)
+    }
+
+    pub fn fs_symlink_metadata(&self) -> io::Result<FsMetadata> {
+        self.fs.symlink_metadata(&self.path)
+    }
+
+    pub fn fs_exists(&self) -> bool {
+        self.fs_metadata().is_ok()
+    }
+
+    pub fn fs_is_file(&self) -> bool {
+        self.fs_metadata().map(|metadata| metadata.is_file()).unwrap_or(false)
+    }
+
+    pub fn fs_is_dir(&self) -> bool {
+        self.fs_metadata().map(|metadata| metadata.is_dir()).unwrap_or(false)
+    }
+
+    pub fn fs_is_symlink(&self) -> bool {
+        self.fs_symlink_metadata().map(|metadata| metadata.is_symlink()).unwrap_or(false)
+    }
+
+    pub fn fs_read(&self) -> io::Result<Vec<u8>> {
+        self.fs.read(&self.path)
+    }
+
+    pub fn fs_read_text(&self) -> io::Result<String> {
+        String::from_utf8(self.fs_read()?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn fs_read_dir_entries(&self) -> io::Result<Vec<(Path, FsFileType)>> {
+        self.fs.read_dir(&self.path)
+    }
+
+    pub fn fs_read_dir_entries_sorted(&self) -> io::Result<Vec<(Path, FsFileType)>> {
+        let mut entries = self.fs_read_dir_entries()?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    pub fn fs_read_dir_names(&self) -> io::Result<Vec<String>> {
+        Ok(self.fs_read_dir_entries()?
+            .into_iter()
+            .filter_map(|(path, _)| path.basename().map(|name| name.to_string()))
+            .collect())
+    }
+
+    pub fn fs_write<T: AsRef<[u8]>>(&self, data: T) -> io::Result<&Self> {
+        self.fs.write(&self.path, data.as_ref())?;
+        Ok(self)
+    }
+
+    pub fn fs_write_text<T: AsRef<str>>(&self, text: T) -> io::Result<&Self> {
+        self.fs_write(text.as_ref().as_bytes())
+    }
+
+    pub fn fs_symlink(&self, target: &Path) -> io::Result<&Self> {
+        self.fs.symlink(&self.path, target)?;
+        Ok(self)
+    }
+
+    pub fn fs_read_link(&self) -> io::Result<Path> {
+        self.fs.read_link(&self.path)
+    }
+
+    pub fn fs_rename(&self, new_path: &Path) -> io::Result<&Self> {
+        self.fs.rename(&self.path, new_path)?;
+        Ok(self)
+    }
+
+    pub fn fs_rm_file(&self) -> io::Result<&Self> {
+        self.fs.remove_file(&self.path)?;
+        Ok(self)
+    }
+
+    pub fn fs_rm(&self) -> io::Result<&Self> {
+        match self.fs_symlink_metadata()?.is_dir() {
+            true => self.fs.remove_dir_all(&self.path),
+            false => self.fs.remove_file(&self.path),
+        }?;
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    // Runs the same scenario against any implementation
+    fn exercise<F: Fs + ?Sized>(fs: &F, root: &Path) {
+        let dir = root.with_fs(fs);
+
+        let file = dir.with_join_str("nested/deep/file.txt");
+        assert!(!file.fs_exists());
+
+        file.fs_create_parent().unwrap();
+        file.fs_write_text("hello").unwrap();
+
+        assert!(file.fs_is_file());
+        assert!(dir.with_join_str("nested").fs_is_dir());
+        assert_eq!(file.fs_read_text().unwrap(), "hello");
+        assert_eq!(file.fs_metadata().unwrap().len, 5);
+
+        let link = dir.with_join_str("nested/link");
+        link.fs_symlink(&Path::from("deep/file.txt")).unwrap();
+
+        assert!(link.fs_is_symlink());
+        assert!(link.fs_is_file());
+        assert_eq!(link.fs_read_link().unwrap(), Path::from("deep/file.txt"));
+        assert_eq!(link.fs_read_text().unwrap(), "hello");
+
+        assert_eq!(dir.with_join_str("nested").fs_read_dir_entries_sorted().unwrap(), vec![
+            (root.with_join_str("nested/deep"), FsFileType::Dir),
+            (root.with_join_str("nested/link"), FsFileType::Symlink),
+        ]);
+
+        file.fs_set_permissions(0o600).unwrap();
+        assert_eq!(file.fs_metadata().unwrap().mode & 0o777, 0o600);
+
+        dir.with_join_str("nested/deep").fs_rename(&root.with_join_str("moved")).unwrap();
+        assert_eq!(dir.with_join_str("moved/file.txt").fs_read_text().unwrap(), "hello");
+        assert!(!link.fs_is_file());
+
+        assert_eq!(dir.with_join_str("nested").fs_create_dir().unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(dir.with_join_str("missing").fs_read().unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        dir.with_join_str("nested").fs_rm().unwrap();
+        dir.with_join_str("moved").fs_rm().unwrap();
+        assert_eq!(dir.fs_read_dir_names().unwrap(), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_real_fs() {
+        let dir = TempDir::new().unwrap();
+        exercise(&RealFs, &dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_memory_fs() {
+        let fs = MemoryFs::new();
+        fs.create_dir_all(&Path::from("/tmp/fixture")).unwrap();
+
+        exercise(&fs, &Path::from("/tmp/fixture"));
+
+        // Also usable as a trait object
+        exercise(&fs as &dyn Fs, &Path::from("/tmp/fixture"));
+    }
+
+    #[test]
+    fn test_memory_fs_errors() {
+        let fs = MemoryFs::new();
+        let root = Path::from("/root");
+
+        assert_eq!(root.with_fs(&fs).with_join_str("a/file").fs_write("").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        root.with_fs(&fs).fs_create_dir().unwrap();
+        root.with_fs(&fs).with_join_str("dir/file").fs_create_parent().unwrap().fs_write("data").unwrap();
+
+        assert_eq!(fs.remove_dir(&Path::from("/root/dir")).unwrap_err().kind(), io::ErrorKind::DirectoryNotEmpty);
+        assert_eq!(fs.read(&Path::from("/root/dir")).unwrap_err().kind(), io::ErrorKind::IsADirectory);
+        assert_eq!(fs.rename(&Path::from("/root"), &Path::from("/root/dir/inner")).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        fs.symlink(&Path::from("/root/loop"), &Path::from("loop")).unwrap();
+        assert!(fs.read(&Path::from("/root/loop")).is_err());
+        assert!(fs.symlink_metadata(&Path::from("/root/loop")).unwrap().is_symlink());
+    }
+}
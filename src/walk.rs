@@ -0,0 +1,819 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display, Formatter};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::{fs, io};
+
+use crate::Path;
+
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: Path,
+    pub error: io::Error,
+}
+
+impl WalkError {
+    fn new(path: &Path, error: io::Error) -> Self {
+        WalkError {
+            path: path.clone(),
+            error,
+        }
+    }
+
+    pub fn into_io_error(self) -> io::Error {
+        self.error
+    }
+}
+
+impl Display for WalkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for WalkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+pub type WalkItem = Result<(Path, fs::FileType), WalkError>;
+
+/// Identifies a directory regardless of the path used to reach it, so that
+/// we can detect symlink loops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DirId {
+    #[cfg(unix)]
+    id: (u64, u64),
+
+    #[cfg(not(unix))]
+    id: std::path::PathBuf,
+}
+
+impl DirId {
+    pub(crate) fn new(path: &Path, metadata: &fs::Metadata) -> io::Result<DirId> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let _ = path;
+            Ok(DirId {id: (metadata.dev(), metadata.ino())})
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = metadata;
+            Ok(DirId {id: fs::canonicalize(path.to_path_buf())?})
+        }
+    }
+}
+
+/// Reads the entries of a directory, optionally sorted by name. Errors that
+/// affect individual entries are returned alongside the successful ones.
+pub(crate) fn read_dir_items(dir: &Path, sorted: bool) -> io::Result<Vec<WalkItem>> {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+
+    for entry in dir.fs_read_dir()? {
+        let item = entry.and_then(|entry| {
+            let file_type = entry.file_type()?;
+            let name = entry.file_name();
+
+            Ok((dir.with_entry_name(&name.to_string_lossy()), file_type))
+        });
+
+        match item {
+            Ok(item) => entries.push(item),
+            Err(err) => errors.push(Err(WalkError::new(dir, err))),
+        }
+    }
+
+    if sorted {
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    let mut items = entries.into_iter()
+        .map(Ok)
+        .collect::<Vec<_>>();
+
+    items.append(&mut errors);
+
+    Ok(items)
+}
+
+/// Returns the type of the entry, resolving it if it's a symlink and symlinks
+/// must be followed. Broken symlinks are reported as symlinks.
+pub(crate) fn resolve_file_type(path: &Path, file_type: fs::FileType, follow_symlinks: bool) -> (fs::FileType, Option<fs::Metadata>) {
+    if !follow_symlinks || !file_type.is_symlink() {
+        return (file_type, None);
+    }
+
+    match fs::metadata(path.to_path_buf()) {
+        Ok(metadata) => (metadata.file_type(), Some(metadata)),
+        Err(_) => (file_type, None),
+    }
+}
+
+struct Frame {
+    items: std::vec::IntoIter<WalkItem>,
+    depth: usize,
+    id: Option<DirId>,
+}
+
+type Filter = Box<dyn FnMut(&Path, &fs::FileType) -> bool>;
+
+/// Recursive directory iterator returned by `Path::fs_walk`. Entries are
+/// yielded depth-first, each directory before its content. Errors are
+/// reported as items and don't abort the walk.
+pub struct Walk {
+    root: Path,
+    min_depth: usize,
+    max_depth: usize,
+    follow_symlinks: bool,
+    sorted: bool,
+    filter: Option<Filter>,
+    started: bool,
+    stack: Vec<Frame>,
+}
+
+impl Debug for Walk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Walk")
+            .field("root", &self.root)
+            .field("min_depth", &self.min_depth)
+            .field("max_depth", &self.max_depth)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("sorted", &self.sorted)
+            .finish()
+    }
+}
+
+impl Walk {
+    pub(crate) fn new(root: &Path) -> Self {
+        Walk {
+            root: root.clone(),
+            min_depth: 0,
+            max_depth: usize::MAX,
+            follow_symlinks: false,
+            sorted: false,
+            filter: None,
+            started: false,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Entries located above this depth aren't yielded (but are still
+    /// traversed). The root itself has depth 0.
+    pub fn min_depth(mut self, depth: usize) -> Self {
+        self.min_depth = depth;
+        self
+    }
+
+    /// Directories located at this depth aren't traversed.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Traverse symlinks pointing to directories. Loops are detected and
+    /// reported as errors.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Yield the entries of each directory sorted by name.
+    pub fn sorted(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Only yield the entries for which the predicate returns true. When it
+    /// returns false for a directory, its content is skipped as well.
+    pub fn filter_entry<F: FnMut(&Path, &fs::FileType) -> bool + 'static>(mut self, filter: F) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    fn accept(&mut self, path: &Path, file_type: &fs::FileType) -> bool {
+        match &mut self.filter {
+            Some(filter) => filter(path, file_type),
+            None => true,
+        }
+    }
+
+    fn push_dir(&mut self, path: &Path, depth: usize, id: Option<DirId>) {
+        let items = match read_dir_items(path, self.sorted) {
+            Ok(items) => items,
+            Err(err) => vec![Err(WalkError::new(path, err))],
+        };
+
+        self.stack.push(Frame {
+            items: items.into_iter(),
+            depth: depth + 1,
+            id,
+        });
+    }
+
+    fn start(&mut self) -> Option<WalkItem> {
+        let root = self.root.clone();
+
+        let metadata = match fs::metadata(root.to_path_buf()) {
+            Ok(metadata) => metadata,
+            Err(err) => return Some(Err(WalkError::new(&root, err))),
+        };
+
+        let file_type = metadata.file_type();
+
+        if !self.accept(&root, &file_type) {
+            return None;
+        }
+
+        if file_type.is_dir() && self.max_depth > 0 {
+            let id = match self.follow_symlinks {
+                true => DirId::new(&root, &metadata).ok(),
+                false => None,
+            };
+
+            self.push_dir(&root, 0, id);
+        }
+
+        match self.min_depth {
+            0 => Some(Ok((root, file_type))),
+            _ => None,
+        }
+    }
+}
+
+impl Iterator for Walk {
+    type Item = WalkItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+
+            if let Some(item) = self.start() {
+                return Some(item);
+            }
+        }
+
+        loop {
+            let frame = self.stack.last_mut()?;
+            let depth = frame.depth;
+
+            let (path, file_type) = match frame.items.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(err)) => return Some(Err(err)),
+
+                None => {
+                    self.stack.pop();
+                    continue;
+                },
+            };
+
+            let (file_type, metadata) = resolve_file_type(&path, file_type, self.follow_symlinks);
+
+            if !self.accept(&path, &file_type) {
+                continue;
+            }
+
+            if file_type.is_dir() && depth < self.max_depth {
+                let id = match (self.follow_symlinks, metadata) {
+                    (true, Some(metadata)) => DirId::new(&path, &metadata).ok(),
+                    (true, None) => fs::metadata(path.to_path_buf()).ok().and_then(|metadata| DirId::new(&path, &metadata).ok()),
+                    (false, _) => None,
+                };
+
+                if id.is_some() && self.stack.iter().any(|frame| frame.id == id) {
+                    return Some(Err(WalkError::new(&path, io::Error::other("Filesystem loop detected"))));
+                }
+
+                self.push_dir(&path, depth, id);
+            }
+
+            if depth >= self.min_depth {
+                return Some(Ok((path, file_type)));
+            }
+        }
+    }
+}
+
+pub(crate) struct Job {
+    pub(crate) path: Path,
+    depth: usize,
+    ancestors: Arc<Vec<DirId>>,
+}
+
+type SharedFilter = Arc<dyn Fn(&Path, &fs::FileType) -> bool + Send + Sync>;
+
+/// Options of the walkers processing multiple directories at once. Each
+/// directory is a job that can be expanded independently from the others.
+#[derive(Clone)]
+pub(crate) struct SharedOptions {
+    pub min_depth: usize,
+    pub max_depth: usize,
+    pub follow_symlinks: bool,
+    pub filter: Option<SharedFilter>,
+}
+
+impl Default for SharedOptions {
+    fn default() -> Self {
+        SharedOptions {
+            min_depth: 0,
+            max_depth: usize::MAX,
+            follow_symlinks: false,
+            filter: None,
+        }
+    }
+}
+
+impl Debug for SharedOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedOptions")
+            .field("min_depth", &self.min_depth)
+            .field("max_depth", &self.max_depth)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .finish()
+    }
+}
+
+impl SharedOptions {
+    fn accept(&self, path: &Path, file_type: &fs::FileType) -> bool {
+        match &self.filter {
+            Some(filter) => filter(path, file_type),
+            None => true,
+        }
+    }
+
+    /// Returns the item to report for the root (if any), and the job to start
+    /// the traversal with (if it must be traversed).
+    pub(crate) fn start(&self, root: &Path) -> (Option<WalkItem>, Option<Job>) {
+        let metadata = match fs::metadata(root.to_path_buf()) {
+            Ok(metadata) => metadata,
+            Err(err) => return (Some(Err(WalkError::new(root, err))), None),
+        };
+
+        let file_type = metadata.file_type();
+
+        if !self.accept(root, &file_type) {
+            return (None, None);
+        }
+
+        let item = match self.min_depth {
+            0 => Some(Ok((root.clone(), file_type))),
+            _ => None,
+        };
+
+        if !file_type.is_dir() || self.max_depth == 0 {
+            return (item, None);
+        }
+
+        let ancestors = match self.follow_symlinks {
+            true => DirId::new(root, &metadata).into_iter().collect(),
+            false => Vec::new(),
+        };
+
+        (item, Some(Job {path: root.clone(), depth: 0, ancestors: Arc::new(ancestors)}))
+    }
+
+    /// Reads the directory of the job, returning the items to report and the
+    /// jobs for the subdirectories to traverse.
+    pub(crate) fn expand(&self, job: &Job) -> (Vec<WalkItem>, Vec<Job>) {
+        let depth = job.depth + 1;
+
+        let items = match read_dir_items(&job.path, false) {
+            Ok(items) => items,
+            Err(err) => vec![Err(WalkError::new(&job.path, err))],
+        };
+
+        let mut reported = Vec::new();
+        let mut jobs = Vec::new();
+
+        for item in items {
+            let item = item.and_then(|(path, file_type)| {
+                let (file_type, metadata) = resolve_file_type(&path, file_type, self.follow_symlinks);
+
+                if !self.accept(&path, &file_type) {
+                    return Ok(None);
+                }
+
+                if file_type.is_dir() && depth < self.max_depth {
+                    let mut ancestors = job.ancestors.clone();
+
+                    if self.follow_symlinks {
+                        let id = match metadata {
+                            Some(metadata) => DirId::new(&path, &metadata).ok(),
+                            None => fs::metadata(path.to_path_buf()).ok().and_then(|metadata| DirId::new(&path, &metadata).ok()),
+                        };
+
+                        if let Some(id) = id {
+                            if ancestors.contains(&id) {
+                                return Err(WalkError::new(&path, io::Error::other("Filesystem loop detected")));
+                            }
+
+                            Arc::make_mut(&mut ancestors).push(id);
+                        }
+                    }
+
+                    jobs.push(Job {path: path.clone(), depth, ancestors});
+                }
+
+                Ok(Some((path, file_type)).filter(|_| depth >= self.min_depth))
+            });
+
+            match item {
+                Ok(Some(entry)) => reported.push(Ok(entry)),
+                Ok(None) => {},
+                Err(err) => reported.push(Err(err)),
+            }
+        }
+
+        (reported, jobs)
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    jobs: VecDeque<Job>,
+    active: usize,
+}
+
+#[derive(Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    cond: Condvar,
+    cancelled: AtomicBool,
+}
+
+impl Queue {
+    fn push(&self, job: Job) {
+        self.state.lock().unwrap().jobs.push_back(job);
+        self.cond.notify_one();
+    }
+
+    fn pop(&self) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            if let Some(job) = state.jobs.pop_front() {
+                state.active += 1;
+                return Some(job);
+            }
+
+            if state.active == 0 {
+                return None;
+            }
+
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    fn done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.active -= 1;
+
+        if state.active == 0 && state.jobs.is_empty() {
+            self.cond.notify_all();
+        }
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.cond.notify_all();
+    }
+}
+
+/// Multi-threaded directory walker returned by `Path::fs_walk_parallel`.
+/// Directories are read concurrently by a pool of threads, and entries are
+/// reported in no particular order unless collected via `collect_sorted`.
+#[derive(Debug)]
+pub struct ParallelWalk {
+    root: Path,
+    options: SharedOptions,
+    threads: usize,
+}
+
+impl ParallelWalk {
+    pub(crate) fn new(root: &Path) -> Self {
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        ParallelWalk {
+            root: root.clone(),
+            options: SharedOptions::default(),
+            threads,
+        }
+    }
+
+    /// Same as `Walk::min_depth`.
+    pub fn min_depth(mut self, depth: usize) -> Self {
+        self.options.min_depth = depth;
+        self
+    }
+
+    /// Same as `Walk::max_depth`.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.options.max_depth = depth;
+        self
+    }
+
+    /// Same as `Walk::follow_symlinks`.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.options.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Number of threads reading directories. Defaults to the available
+    /// parallelism.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Same as `Walk::filter_entry`, except that the predicate may be called
+    /// from multiple threads at once. If it panics, the walk stops and the
+    /// panic is reported as an error on the directory being read.
+    pub fn filter_entry<F: Fn(&Path, &fs::FileType) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.options.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Starts the traversal in the background and returns the channel the
+    /// entries will be sent through. Dropping the receiver stops the walk.
+    pub fn receiver(self) -> mpsc::Receiver<WalkItem> {
+        let (sender, receiver) = mpsc::channel();
+        let (item, job) = self.options.start(&self.root);
+
+        if let Some(item) = item {
+            let _ = sender.send(item);
+        }
+
+        let Some(job) = job else {
+            return receiver;
+        };
+
+        let queue = Arc::new(Queue::default());
+        queue.push(job);
+
+        for _ in 0..self.threads {
+            let queue = queue.clone();
+            let sender = sender.clone();
+            let options = self.options.clone();
+
+            std::thread::spawn(move || {
+                while let Some(job) = queue.pop() {
+                    // A panicking filter must neither leave the job active
+                    // (which would hang the other workers) nor go unnoticed
+                    let expanded = panic::catch_unwind(AssertUnwindSafe(|| options.expand(&job)));
+
+                    let (items, jobs) = match expanded {
+                        Ok(expanded) => expanded,
+
+                        Err(payload) => {
+                            queue.cancel();
+                            (vec![Err(filter_panic_error(&job.path, &*payload))], Vec::new())
+                        },
+                    };
+
+                    for job in jobs {
+                        queue.push(job);
+                    }
+
+                    for item in items {
+                        if sender.send(item).is_err() {
+                            queue.cancel();
+                            break;
+                        }
+                    }
+
+                    queue.done();
+                }
+            });
+        }
+
+        receiver
+    }
+
+    /// Runs the traversal, calling the callback on the current thread for
+    /// each entry as soon as it's found.
+    pub fn for_each<F: FnMut(WalkItem)>(self, callback: F) {
+        self.receiver().into_iter().for_each(callback);
+    }
+
+    /// Runs the traversal and returns the entries in the same order as a
+    /// sorted sequential walk would.
+    pub fn collect_sorted(self) -> Vec<WalkItem> {
+        let mut items = self.receiver().into_iter().collect::<Vec<_>>();
+        sort_items(&mut items);
+        items
+    }
+}
+
+/// Sorts the items of an unordered walk in the order of a sorted sequential
+/// walk, errors coming after the entry they relate to.
+pub(crate) fn sort_items(items: &mut [WalkItem]) {
+    items.sort_by(|a, b| {
+        let (a_path, b_path) = (item_path(a), item_path(b));
+
+        a_path.as_str().split('/').cmp(b_path.as_str().split('/'))
+            .then_with(|| a.is_err().cmp(&b.is_err()))
+    });
+}
+
+/// Reports a panic that happened while reading the directory at `path`.
+pub(crate) fn filter_panic_error(path: &Path, payload: &(dyn std::any::Any + Send)) -> WalkError {
+    WalkError::new(path, io::Error::other(format!("Walk filter panicked: {}", panic_message(payload))))
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+fn item_path(item: &WalkItem) -> &Path {
+    match item {
+        Ok((path, _)) => path,
+        Err(err) => &err.path,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn make_tree() -> Path {
+        let dir = Path::temp_dir().unwrap();
+
+        dir.with_join_str("a/b/c").fs_create_dir_all().unwrap();
+        dir.with_join_str("a/file1").fs_write("1").unwrap();
+        dir.with_join_str("a/b/file2").fs_write("2").unwrap();
+        dir.with_join_str("a/b/c/file3").fs_write("3").unwrap();
+        dir.with_join_str("z").fs_write("z").unwrap();
+
+        dir
+    }
+
+    fn relative(dir: &Path, walk: Walk) -> Vec<String> {
+        walk.map(|item| item.unwrap().0.relative_to(dir).to_string()).collect()
+    }
+
+    #[test]
+    fn test_walk_sorted() {
+        let dir = make_tree();
+
+        assert_eq!(relative(&dir, dir.fs_walk().sorted(true)), vec![
+            "", "a", "a/b", "a/b/c", "a/b/c/file3", "a/b/file2", "a/file1", "z",
+        ]);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_walk_depth() {
+        let dir = make_tree();
+
+        assert_eq!(relative(&dir, dir.fs_walk().sorted(true).min_depth(1).max_depth(2)), vec![
+            "a", "a/b", "a/file1", "z",
+        ]);
+
+        assert_eq!(relative(&dir, dir.fs_walk().sorted(true).min_depth(3)), vec![
+            "a/b/c", "a/b/c/file3", "a/b/file2",
+        ]);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_walk_filter() {
+        let dir = make_tree();
+
+        let walk = dir.fs_walk()
+            .sorted(true)
+            .min_depth(1)
+            .filter_entry(|path, _| path.basename() != Some("b"));
+
+        assert_eq!(relative(&dir, walk), vec!["a", "a/file1", "z"]);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_walk_symlinks() {
+        let dir = make_tree();
+
+        std::os::unix::fs::symlink("../..", dir.with_join_str("a/b/loop").to_path_buf()).unwrap();
+
+        let entries = dir.fs_walk().sorted(true).collect::<Vec<_>>();
+        assert!(entries.iter().all(|item| item.is_ok()));
+        assert_eq!(entries.len(), 9);
+
+        let entries = dir.fs_walk().sorted(true).follow_symlinks(true).collect::<Vec<_>>();
+        let errors = entries.iter().filter(|item| item.is_err()).count();
+
+        assert_eq!(errors, 1);
+        assert_eq!(entries.len(), 9);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_walk_errors() {
+        let dir = Path::temp_dir().unwrap();
+        let missing = dir.with_join_str("missing");
+
+        let entries = missing.fs_walk().collect::<Vec<_>>();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].as_ref().unwrap_err().path, missing);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_walk_parallel_sorted() {
+        let dir = make_tree();
+
+        let sequential = relative(&dir, dir.fs_walk().sorted(true));
+        let parallel = dir.fs_walk_parallel()
+            .threads(4)
+            .collect_sorted()
+            .into_iter()
+            .map(|item| item.unwrap().0.relative_to(&dir).to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(parallel, sequential);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_walk_parallel_options() {
+        let dir = make_tree();
+
+        let parallel = dir.fs_walk_parallel()
+            .min_depth(1)
+            .max_depth(2)
+            .filter_entry(|path, _| path.basename() != Some("b"))
+            .collect_sorted()
+            .into_iter()
+            .map(|item| item.unwrap().0.relative_to(&dir).to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(parallel, vec!["a", "a/file1", "z"]);
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_walk_parallel_symlinks() {
+        let dir = make_tree();
+
+        std::os::unix::fs::symlink("../..", dir.with_join_str("a/b/loop").to_path_buf()).unwrap();
+
+        let mut count = 0;
+        let mut errors = 0;
+
+        dir.fs_walk_parallel().follow_symlinks(true).for_each(|item| {
+            count += 1;
+            errors += item.is_err() as usize;
+        });
+
+        assert_eq!((count, errors), (9, 1));
+
+        dir.fs_rm().unwrap();
+    }
+
+    #[test]
+    fn test_walk_parallel_filter_panic() {
+        let dir = make_tree();
+
+        let items = dir.fs_walk_parallel()
+            .threads(2)
+            .filter_entry(|path, _| {
+                assert_ne!(path.basename(), Some("c"), "unexpected entry");
+                true
+            })
+            .collect_sorted();
+
+        let errors = items.iter()
+            .filter_map(|item| item.as_ref().err())
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, dir.with_join_str("a/b"));
+        assert!(errors[0].error.to_string().contains("unexpected entry"));
+
+        dir.fs_rm().unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::walk::{filter_panic_error, Job, SharedOptions, WalkItem};
+use crate::Path;
+
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Asynchronous directory walker returned by `Path::fs_walk_stream`. Up to
+/// `concurrency` directories are read at once on tokio's blocking thread
+/// pool, and entries are yielded in no particular order.
+///
+/// The traversal starts when the stream is first polled, which must happen
+/// from within a tokio runtime. Dropping the stream cancels it, and a
+/// panicking filter stops it after yielding an error, like `ParallelWalk`.
+#[derive(Debug)]
+pub struct WalkStream {
+    root: Path,
+    options: SharedOptions,
+    concurrency: usize,
+    receiver: Option<mpsc::Receiver<WalkItem>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl WalkStream {
+    pub(crate) fn new(root: &Path) -> Self {
+        WalkStream {
+            root: root.clone(),
+            options: SharedOptions::default(),
+            concurrency: 8,
+            receiver: None,
+            task: None,
+        }
+    }
+
+    /// Same as `Walk::min_depth`.
+    pub fn min_depth(mut self, depth: usize) -> Self {
+        self.options.min_depth = depth;
+        self
+    }
+
+    /// Same as `Walk::max_depth`.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.options.max_depth = depth;
+        self
+    }
+
+    /// Same as `Walk::follow_symlinks`.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.options.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Maximum number of directories being read at the same time. Defaults
+    /// to 8.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Same as `Walk::filter_entry`, except that the predicate may be called
+    /// from multiple threads at once.
+    pub fn filter_entry<F: Fn(&Path, &fs::FileType) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.options.filter = Some(Arc::new(filter));
+        self
+    }
+
+    fn start(&mut self) -> &mut mpsc::Receiver<WalkItem> {
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+
+        self.task = Some(tokio::spawn(drive(self.root.clone(), self.options.clone(), self.concurrency, sender)));
+        self.receiver.insert(receiver)
+    }
+}
+
+async fn drive(root: Path, options: SharedOptions, concurrency: usize, sender: mpsc::Sender<WalkItem>) {
+    let start_options = options.clone();
+
+    let started = tokio::task::spawn_blocking(move || {
+        panic::catch_unwind(AssertUnwindSafe(|| start_options.start(&root)))
+            .map_err(|payload| filter_panic_error(&root, &*payload))
+    }).await;
+
+    let (item, job) = match started {
+        Ok(Ok(started)) => started,
+
+        Ok(Err(error)) => {
+            let _ = sender.send(Err(error)).await;
+            return;
+        },
+
+        Err(_) => return,
+    };
+
+    if let Some(item) = item {
+        if sender.send(item).await.is_err() {
+            return;
+        }
+    }
+
+    let mut pending = job.into_iter().collect::<VecDeque<Job>>();
+    let mut running = JoinSet::new();
+
+    loop {
+        while running.len() < concurrency {
+            let Some(job) = pending.pop_front() else {
+                break;
+            };
+
+            let options = options.clone();
+
+            running.spawn_blocking(move || {
+                panic::catch_unwind(AssertUnwindSafe(|| options.expand(&job)))
+                    .map_err(|payload| filter_panic_error(&job.path, &*payload))
+            });
+        }
+
+        let Some(result) = running.join_next().await else {
+            return;
+        };
+
+        // Tasks are only cancelled when the stream itself is dropped
+        let (items, jobs) = match result {
+            Ok(Ok(expanded)) => expanded,
+
+            Ok(Err(error)) => {
+                let _ = sender.send(Err(error)).await;
+                return;
+            },
+
+            Err(_) => return,
+        };
+
+        pending.extend(jobs);
+
+        for item in items {
+            if sender.send(item).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Stream for WalkStream {
+    type Item = WalkItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WalkItem>> {
+        let this = &mut *self;
+
+        let receiver = match &mut this.receiver {
+            Some(receiver) => receiver,
+            None => this.start(),
+        };
+
+        receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for WalkStream {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::future::poll_fn;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::walk::sort_items;
+    use crate::TempDir;
+
+    async fn next(stream: &mut WalkStream) -> Option<WalkItem> {
+        poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+    }
+
+    async fn collect_relative(dir: &Path, mut stream: WalkStream) -> Vec<String> {
+        let mut items = Vec::new();
+
+        while let Some(item) = next(&mut stream).await {
+            items.push(item);
+        }
+
+        sort_items(&mut items);
+
+        items.into_iter()
+            .map(|item| item.unwrap().0.relative_to(dir).to_string())
+            .collect()
+    }
+
+    fn make_tree(dir: &Path) {
+        dir.with_join_str("a/b/c").fs_create_dir_all().unwrap();
+        dir.with_join_str("a/file1").fs_write("1").unwrap();
+        dir.with_join_str("a/b/file2").fs_write("2").unwrap();
+        dir.with_join_str("a/b/c/file3").fs_write("3").unwrap();
+        dir.with_join_str("z").fs_write("z").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_walk_stream_sorted() {
+        let dir = TempDir::new().unwrap();
+        make_tree(&dir);
+
+        let sequential = dir.fs_walk()
+            .sorted(true)
+            .map(|item| item.unwrap().0.relative_to(&dir).to_string())
+            .collect::<Vec<_>>();
+
+        let streamed = collect_relative(&dir, dir.fs_walk_stream().concurrency(2)).await;
+        assert_eq!(streamed, sequential);
+    }
+
+    #[tokio::test]
+    async fn test_walk_stream_options() {
+        let dir = TempDir::new().unwrap();
+        make_tree(&dir);
+
+        let stream = dir.fs_walk_stream()
+            .min_depth(1)
+            .max_depth(2)
+            .filter_entry(|path, _| path.basename() != Some("b"));
+
+        assert_eq!(collect_relative(&dir, stream).await, vec!["a", "a/file1", "z"]);
+    }
+
+    #[tokio::test]
+    async fn test_walk_stream_filter_panic() {
+        let dir = TempDir::new().unwrap();
+        make_tree(&dir);
+
+        let mut stream = dir.fs_walk_stream()
+            .concurrency(2)
+            .filter_entry(|path, _| {
+                assert_ne!(path.basename(), Some("c"), "unexpected entry");
+                true
+            });
+
+        let mut items = Vec::new();
+
+        while let Some(item) = next(&mut stream).await {
+            items.push(item);
+        }
+
+        let errors = items.iter()
+            .filter_map(|item| item.as_ref().err())
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, dir.with_join_str("a/b"));
+        assert!(errors[0].error.to_string().contains("unexpected entry"));
+
+        let mut stream = dir.fs_walk_stream()
+            .filter_entry(|_, _| panic!("unexpected root"));
+
+        let error = next(&mut stream).await.unwrap().unwrap_err();
+        assert_eq!(error.path, *dir);
+        assert!(error.error.to_string().contains("unexpected root"));
+        assert!(next(&mut stream).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_walk_stream_cancel() {
+        let dir = TempDir::new().unwrap();
+
+        for i in 0..20 {
+            dir.with_join_str(format!("dir{}", i)).fs_create_dir().unwrap();
+
+            for j in 0..50 {
+                dir.with_join_str(format!("dir{}/file{}", i, j)).fs_write("").unwrap();
+            }
+        }
+
+        let visited = Arc::new(AtomicUsize::new(0));
+        let counter = visited.clone();
+
+        let mut stream = dir.fs_walk_stream()
+            .concurrency(1)
+            .filter_entry(move |_, _| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                true
+            });
+
+        assert!(next(&mut stream).await.is_some());
+        drop(stream);
+
+        // The driver blocks once the channel is full, and never resumes once
+        // aborted, so the walk can't have reached all the 1021 entries
+        tokio::task::spawn_blocking(|| std::thread::sleep(std::time::Duration::from_millis(100))).await.unwrap();
+        assert!(visited.load(Ordering::Relaxed) < 1021);
+    }
+}
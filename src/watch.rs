@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::{Path, Trie};
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_MODIFY
+    | libc::IN_ATTRIB
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF;
+
+const EVENT_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+// A batch keeps collecting events as long as they keep arriving within the
+// debounce delay, but never for longer than this many times the delay.
+const MAX_DEBOUNCE_FACTOR: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    Create(Path),
+    Modify(Path),
+    Remove(Path),
+
+    Rename {
+        from: Path,
+        to: Path,
+    },
+
+    /// The kernel queue overflowed and some events were lost; the watched
+    /// directories should be rescanned.
+    Rescan,
+}
+
+impl WatchEvent {
+    /// Returns the path affected by the event; for renames, the destination.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            WatchEvent::Create(path) | WatchEvent::Modify(path) | WatchEvent::Remove(path) => Some(path),
+            WatchEvent::Rename {to, ..} => Some(to),
+            WatchEvent::Rescan => None,
+        }
+    }
+
+    /// Returns the value associated with the closest ancestor of the event's
+    /// path in the trie (for example, the workspace containing the file).
+    pub fn owner<'t, T>(&self, trie: &'t Trie<T>) -> Option<&'t T> {
+        self.path().and_then(|path| trie.get_ancestor_value(path))
+    }
+}
+
+/// Events collected during a debounce window. Successive events affecting the
+/// same path are merged together, and moves are paired into renames.
+#[derive(Default)]
+struct Batch {
+    events: Vec<Option<WatchEvent>>,
+    index: HashMap<Path, usize>,
+    moves: HashMap<u32, Path>,
+}
+
+impl Batch {
+    fn push(&mut self, event: WatchEvent) {
+        let path = match &event {
+            WatchEvent::Create(path) | WatchEvent::Modify(path) | WatchEvent::Remove(path) => path.clone(),
+
+            _ => {
+                self.events.push(Some(event));
+                return;
+            },
+        };
+
+        let Some(&index) = self.index.get(&path) else {
+            self.index.insert(path, self.events.len());
+            self.events.push(Some(event));
+            return;
+        };
+
+        let merged = match (self.events[index].take(), event) {
+            (Some(WatchEvent::Create(_)), WatchEvent::Modify(_)) => Some(WatchEvent::Create(path.clone())),
+            (Some(WatchEvent::Create(_)), WatchEvent::Remove(_)) => None,
+            (Some(WatchEvent::Remove(_)), WatchEvent::Create(_)) => Some(WatchEvent::Modify(path.clone())),
+            (_, event) => Some(event),
+        };
+
+        if merged.is_none() {
+            self.index.remove(&path);
+        }
+
+        self.events[index] = merged;
+    }
+
+    fn move_from(&mut self, cookie: u32, path: Path) {
+        self.moves.insert(cookie, path);
+    }
+
+    fn move_to(&mut self, cookie: u32, path: Path) {
+        match self.moves.remove(&cookie) {
+            Some(from) => {
+                // Later events must not be merged with those preceding the rename
+                self.index.remove(&from);
+                self.index.remove(&path);
+
+                self.events.push(Some(WatchEvent::Rename {from, to: path}));
+            },
+
+            None => {
+                self.push(WatchEvent::Create(path));
+            },
+        }
+    }
+
+    fn finish(mut self) -> Vec<WatchEvent> {
+        // Files moved outside of the watched directories
+        let mut moves = std::mem::take(&mut self.moves).into_iter().collect::<Vec<_>>();
+        moves.sort();
+
+        for (_, from) in moves {
+            self.push(WatchEvent::Remove(from));
+        }
+
+        self.events.into_iter().flatten().collect()
+    }
+}
+
+#[derive(Debug)]
+struct WatchEntry {
+    path: Path,
+    recursive: bool,
+}
+
+/// Inotify-based watcher reporting debounced events. Paths are built from the
+/// watched paths, so they're normalized the same way.
+#[derive(Debug)]
+pub struct Watcher {
+    fd: OwnedFd,
+    watches: HashMap<i32, WatchEntry>,
+    debounce: Duration,
+}
+
+impl Watcher {
+    pub fn new(debounce: Duration) -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Watcher {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            watches: HashMap::new(),
+            debounce,
+        })
+    }
+
+    /// Starts watching the path. When recursive, all the directories it
+    /// contains (including those created later on) are watched as well.
+    /// Symlinks aren't followed.
+    pub fn watch(&mut self, path: &Path, recursive: bool) -> io::Result<()> {
+        self.add_watch(path, recursive)?;
+
+        if recursive && path.fs_is_dir() {
+            let dirs = path.fs_walk()
+                .min_depth(1)
+                .filter_entry(|_, file_type| file_type.is_dir());
+
+            for entry in dirs {
+                let (dir, _) = entry.map_err(|err| err.into_io_error())?;
+                self.add_watch(&dir, true)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops watching the path, along with the directories it contains if it
+    /// was watched recursively.
+    pub fn unwatch(&mut self, path: &Path) -> io::Result<()> {
+        let prefix = format!("{}/", path.as_str().trim_end_matches('/'));
+
+        let wds = self.watches.iter()
+            .filter(|(_, entry)| entry.path == *path || (entry.recursive && entry.path.as_str().starts_with(&prefix)))
+            .map(|(wd, _)| *wd)
+            .collect::<Vec<_>>();
+
+        for wd in wds {
+            self.watches.remove(&wd);
+
+            // Fails if the kernel already dropped the watch, which is fine
+            unsafe { libc::inotify_rm_watch(self.fd.as_raw_fd(), wd) };
+        }
+
+        Ok(())
+    }
+
+    /// Waits for events, up to `timeout` (forever if `None`). Once the first
+    /// event arrives, keeps collecting until no new event is received for the
+    /// debounce delay, then returns them merged together. Returns an empty
+    /// list on timeout.
+    pub fn next_events(&mut self, timeout: Option<Duration>) -> io::Result<Vec<WatchEvent>> {
+        let mut batch = Batch::default();
+
+        if !self.wait(timeout)? {
+            return Ok(Vec::new());
+        }
+
+        let started = Instant::now();
+        let max_duration = self.debounce * MAX_DEBOUNCE_FACTOR;
+
+        loop {
+            self.read_into(&mut batch)?;
+
+            let elapsed = started.elapsed();
+
+            if elapsed >= max_duration || !self.wait(Some(self.debounce.min(max_duration - elapsed)))? {
+                break;
+            }
+        }
+
+        Ok(batch.finish())
+    }
+
+    /// Same as `next_events`, but each event is returned along with the value
+    /// owning its path in the trie (see `WatchEvent::owner`).
+    pub fn next_events_routed<'t, T>(&mut self, trie: &'t Trie<T>, timeout: Option<Duration>) -> io::Result<Vec<(WatchEvent, Option<&'t T>)>> {
+        Ok(self.next_events(timeout)?
+            .into_iter()
+            .map(|event| {
+                let owner = event.owner(trie);
+                (event, owner)
+            })
+            .collect())
+    }
+
+    fn add_watch(&mut self, path: &Path, recursive: bool) -> io::Result<()> {
+        let c_path = CString::new(path.as_str())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), WATCH_MASK) };
+
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.watches.insert(wd, WatchEntry {path: path.clone(), recursive});
+
+        Ok(())
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let timeout_ms = match timeout {
+            Some(timeout) => timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        loop {
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                0 => return Ok(false),
+                n if n > 0 => return Ok(true),
+
+                _ => {
+                    let err = io::Error::last_os_error();
+
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                },
+            }
+        }
+    }
+
+    fn read_into(&mut self, batch: &mut Batch) -> io::Result<()> {
+        let mut buf = vec![0u8; 64 * 1024];
+
+        loop {
+            let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+
+            if n < 0 {
+                let err = io::Error::last_os_error();
+
+                match err.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(()),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
+            }
+
+            let n = n as usize;
+            let mut offset = 0;
+
+            while offset + EVENT_SIZE <= n {
+                let event = unsafe {
+                    std::ptr::read_unaligned(buf.as_ptr().add(offset).cast::<libc::inotify_event>())
+                };
+
+                let name_start = offset + EVENT_SIZE;
+                let name_end = name_start + event.len as usize;
+
+                let name = &buf[name_start..name_end];
+                let name = &name[..name.iter().position(|c| *c == 0).unwrap_or(name.len())];
+
+                self.handle(&event, &String::from_utf8_lossy(name), batch);
+
+                offset = name_end;
+            }
+        }
+    }
+
+    fn handle(&mut self, event: &libc::inotify_event, name: &str, batch: &mut Batch) {
+        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+            batch.push(WatchEvent::Rescan);
+            return;
+        }
+
+        let Some(entry) = self.watches.get(&event.wd) else {
+            return;
+        };
+
+        let dir = entry.path.clone();
+        let recursive = entry.recursive;
+
+        if event.mask & libc::IN_IGNORED != 0 {
+            self.watches.remove(&event.wd);
+            return;
+        }
+
+        let path = match name.is_empty() {
+            true => dir,
+            false => dir.with_entry_name(name),
+        };
+
+        let is_dir = event.mask & libc::IN_ISDIR != 0;
+
+        if event.mask & libc::IN_CREATE != 0 {
+            batch.push(WatchEvent::Create(path.clone()));
+
+            // Entries created before the watch got added would be missed
+            if is_dir && recursive && self.watch(&path, true).is_ok() {
+                for entry in path.fs_walk().sorted(true).min_depth(1).flatten() {
+                    batch.push(WatchEvent::Create(entry.0));
+                }
+            }
+        }
+
+        if event.mask & (libc::IN_MODIFY | libc::IN_ATTRIB) != 0 && !is_dir {
+            batch.push(WatchEvent::Modify(path.clone()));
+        }
+
+        if event.mask & (libc::IN_DELETE | libc::IN_DELETE_SELF) != 0 {
+            batch.push(WatchEvent::Remove(path.clone()));
+        }
+
+        if event.mask & libc::IN_MOVED_FROM != 0 {
+            if is_dir {
+                let _ = self.unwatch(&path);
+            }
+
+            batch.move_from(event.cookie, path.clone());
+        }
+
+        if event.mask & libc::IN_MOVED_TO != 0 {
+            if is_dir && recursive {
+                let _ = self.watch(&path, true);
+            }
+
+            batch.move_to(event.cookie, path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TempDir;
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(2));
+
+    fn make_watcher(dir: &Path) -> Watcher {
+        let mut watcher = Watcher::new(Duration::from_millis(50)).unwrap();
+        watcher.watch(dir, true).unwrap();
+        watcher
+    }
+
+    #[test]
+    fn test_watch_create_modify_remove() {
+        let dir = TempDir::new().unwrap();
+        let mut watcher = make_watcher(&dir);
+
+        let file = dir.with_join_str("file");
+        file.fs_write("hello").unwrap();
+
+        assert_eq!(watcher.next_events(TIMEOUT).unwrap(), vec![WatchEvent::Create(file.clone())]);
+
+        file.fs_write("world").unwrap();
+        assert_eq!(watcher.next_events(TIMEOUT).unwrap(), vec![WatchEvent::Modify(file.clone())]);
+
+        file.fs_rm_file().unwrap();
+        assert_eq!(watcher.next_events(TIMEOUT).unwrap(), vec![WatchEvent::Remove(file.clone())]);
+
+        // Created and removed within the same window
+        file.fs_write("hello").unwrap();
+        file.fs_rm_file().unwrap();
+        dir.with_join_str("other").fs_write("").unwrap();
+
+        assert_eq!(watcher.next_events(TIMEOUT).unwrap(), vec![WatchEvent::Create(dir.with_join_str("other"))]);
+        assert_eq!(watcher.next_events(Some(Duration::from_millis(10))).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_watch_recursive() {
+        let dir = TempDir::new().unwrap();
+        dir.with_join_str("existing").fs_create_dir().unwrap();
+
+        let mut watcher = make_watcher(&dir);
+
+        let file = dir.with_join_str("existing/file");
+        file.fs_write("").unwrap();
+        assert_eq!(watcher.next_events(TIMEOUT).unwrap(), vec![WatchEvent::Create(file)]);
+
+        let nested = dir.with_join_str("new/nested");
+        nested.fs_create_dir_all().unwrap();
+        nested.with_join_str("file").fs_write("").unwrap();
+
+        let mut events = watcher.next_events(TIMEOUT).unwrap();
+
+        // The nested file may or may not be reported before its directory got watched
+        events.dedup();
+
+        assert_eq!(events, vec![
+            WatchEvent::Create(dir.with_join_str("new")),
+            WatchEvent::Create(dir.with_join_str("new/nested")),
+            WatchEvent::Create(dir.with_join_str("new/nested/file")),
+        ]);
+    }
+
+    #[test]
+    fn test_watch_rename() {
+        let dir = TempDir::new().unwrap();
+        dir.with_join_str("a").fs_create_dir().unwrap();
+        dir.with_join_str("file").fs_write("").unwrap();
+
+        let outside = TempDir::new().unwrap();
+        let mut watcher = make_watcher(&dir);
+
+        dir.with_join_str("file").fs_rename(&dir.with_join_str("renamed")).unwrap();
+
+        assert_eq!(watcher.next_events(TIMEOUT).unwrap(), vec![WatchEvent::Rename {
+            from: dir.with_join_str("file"),
+            to: dir.with_join_str("renamed"),
+        }]);
+
+        dir.with_join_str("a").fs_rename(&dir.with_join_str("b")).unwrap();
+        watcher.next_events(TIMEOUT).unwrap();
+
+        // Directories keep being watched under their new name
+        dir.with_join_str("b/file").fs_write("").unwrap();
+        assert_eq!(watcher.next_events(TIMEOUT).unwrap(), vec![WatchEvent::Create(dir.with_join_str("b/file"))]);
+
+        dir.with_join_str("renamed").fs_rename(&outside.with_join_str("file")).unwrap();
+        assert_eq!(watcher.next_events(TIMEOUT).unwrap(), vec![WatchEvent::Remove(dir.with_join_str("renamed"))]);
+    }
+
+    #[test]
+    fn test_watch_routing() {
+        let dir = TempDir::new().unwrap();
+        dir.with_join_str("packages/foo").fs_create_dir_all().unwrap();
+        dir.with_join_str("packages/bar").fs_create_dir_all().unwrap();
+
+        let mut trie = Trie::default();
+        trie.insert(dir.with_join_str("packages/foo"), "foo");
+        trie.insert(dir.with_join_str("packages/bar"), "bar");
+
+        let mut watcher = make_watcher(&dir);
+
+        dir.with_join_str("packages/foo/index.js").fs_write("").unwrap();
+        dir.with_join_str("README.md").fs_write("").unwrap();
+
+        let mut routed = watcher.next_events_routed(&trie, TIMEOUT).unwrap()
+            .into_iter()
+            .map(|(event, owner)| (event.path().unwrap().clone(), owner.copied()))
+            .collect::<Vec<_>>();
+
+        routed.sort();
+
+        assert_eq!(routed, vec![
+            (dir.with_join_str("README.md"), None),
+            (dir.with_join_str("packages/foo/index.js"), Some("foo")),
+        ]);
+    }
+}